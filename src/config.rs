@@ -1,13 +1,499 @@
-use gumdrop::Options;
 use indexmap::IndexMap;
+use regex::Regex;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A regular expression, written as a string in the config file.
+#[derive(Clone, Debug)]
+pub struct Pattern(pub Regex);
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(Self)
+    }
+}
+
+impl std::str::FromStr for Pattern {
+    type Err = regex::Error;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        Pattern::new(pattern)
+    }
+}
+
+impl std::ops::Deref for Pattern {
+    type Target = Regex;
+
+    fn deref(&self) -> &Regex {
+        &self.0
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Pattern::new(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A kind of string, opened and closed by the same delimiter.
+/// In the config file, it can be written either as a table or as its delimiter alone.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct StringConfig {
+    pub delimiter: String,
+
+    /// Prefixes that must precede the opening delimiter for a string to be of this kind, like `f` for Python's f-strings.
+    /// Defaults to no prefix.
+    #[serde(default)]
+    pub prefixes: Vec<String>,
+
+    /// The opener and closer of the regions of code that can be embedded within the string, like `${` and `}` in JS.
+    /// Within those regions, brackets opened with the last character of the opener are counted,
+    /// so that a closer matching one of them does not end the region early.
+    /// Within the string itself, a doubled opener stands for itself and doesn't open a region, like `{{` in Python.
+    #[serde(default)]
+    pub interpolation: Option<(String, String)>,
+
+    /// The token escaping the character that follows it within the string, like `\` in `"\""`.
+    /// Defaults to the language's `escape`; an empty escape disables escaping.
+    #[serde(default)]
+    pub escape: Option<String>,
+
+    /// Whether a doubled delimiter stands for the delimiter itself, as in SQL's `'it''s'`.
+    #[serde(default)]
+    pub doubled: bool,
+}
+
+impl StringConfig {
+    pub fn new(delimiter: &str) -> Self {
+        Self {
+            delimiter: delimiter.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefixes.push(prefix.to_string());
+        self
+    }
+
+    pub fn interpolation(mut self, start: &str, end: &str) -> Self {
+        self.interpolation = Some((start.to_string(), end.to_string()));
+        self
+    }
+
+    pub fn escape(mut self, escape: &str) -> Self {
+        self.escape = Some(escape.to_string());
+        self
+    }
+
+    pub fn doubled(mut self, doubled: bool) -> Self {
+        self.doubled = doubled;
+        self
+    }
+}
+
+impl From<&str> for StringConfig {
+    fn from(delimiter: &str) -> Self {
+        Self::new(delimiter)
+    }
+}
+
+impl From<String> for StringConfig {
+    fn from(delimiter: String) -> Self {
+        Self {
+            delimiter,
+            ..Default::default()
+        }
+    }
+}
+
+/// An opener/closer pair, where the closer depends on what the opener matched.
+/// The opener is a regular expression, and the closer may refer to its capture groups (as in `"$1"`).
+/// In the config file, it is written as a `[start, end]` pair.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "(Pattern, String)", into = "(Pattern, String)")]
+pub struct RawDelimiter {
+    pub start: Pattern,
+    pub end: String,
+}
+
+impl RawDelimiter {
+    /// Panics if `start` is not a valid regular expression.
+    pub fn new(start: &str, end: &str) -> Self {
+        Self {
+            start: Pattern::new(start).expect("Invalid raw delimiter opener"),
+            end: end.to_string(),
+        }
+    }
+}
+
+impl From<(Pattern, String)> for RawDelimiter {
+    fn from((start, end): (Pattern, String)) -> Self {
+        Self { start, end }
+    }
+}
+
+impl From<RawDelimiter> for (Pattern, String) {
+    fn from(delimiter: RawDelimiter) -> Self {
+        (delimiter.start, delimiter.end)
+    }
+}
+
+/// A kind of heredoc, a string which begins on the line after its opener,
+/// and ends at the first line holding only the terminator captured by the opener.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HeredocConfig {
+    /// Regular expression matching the opener, whose `terminator` group captures the terminator.
+    /// Like other tokens, it will not match if it overlaps with a blacklisted token.
+    pub start: Pattern,
+
+    /// Whether the terminator may be indented. When set to `false`, it may still be indented
+    /// if the opener has an `indent` group and it matched, as with `<<-EOF` or `<<~EOF`.
+    #[serde(default)]
+    pub indented: bool,
+
+    /// Whether code may follow the terminator on its line, like the `;` in PHP's `EOT;`.
+    #[serde(default)]
+    pub trailing_code: bool,
+}
+
+impl HeredocConfig {
+    /// Panics if `start` is not a valid regular expression.
+    pub fn new(start: &str) -> Self {
+        Self {
+            start: Pattern::new(start).expect("Invalid heredoc opener"),
+            indented: false,
+            trailing_code: false,
+        }
+    }
+
+    pub fn indented(mut self, indented: bool) -> Self {
+        self.indented = indented;
+        self
+    }
+
+    pub fn trailing_code(mut self, trailing_code: bool) -> Self {
+        self.trailing_code = trailing_code;
+        self
+    }
+}
+
+/// A region of the file, like `<script> … </script>` in HTML, within which the rules of another language apply.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RegionConfig {
+    /// Regular expression matching the opener, which is part of the host language's code.
+    pub start: Pattern,
+
+    /// The closer, which may refer to the capture groups of `start`.
+    /// The region ends at the first occurrence of the closer, wherever it lies in the embedded language,
+    /// or at the end of its line if the closer is empty.
+    pub end: String,
+
+    /// Whether the closer must stand on a line of its own, up to surrounding whitespace,
+    /// like the closing fence of Markdown's code blocks. It may then be followed by more of its last character,
+    /// since such a fence may be longer than the opening one.
+    #[serde(default)]
+    pub own_line: bool,
+
+    /// The name or one of the extensions of the language whose rules apply within the region,
+    /// which may refer to the capture groups of `start`, like the info string of Markdown's code blocks.
+    /// The language is looked up in [`LangConfig::embedded_langs`]; if it can't be found, the region is kept as-is.
+    pub lang: String,
+}
+
+impl RegionConfig {
+    /// Panics if `start` is not a valid regular expression.
+    pub fn new(start: &str, end: &str, lang: &str) -> Self {
+        Self {
+            start: Pattern::new(start).expect("Invalid region opener"),
+            end: end.to_string(),
+            own_line: false,
+            lang: lang.to_string(),
+        }
+    }
+
+    pub fn own_line(mut self, own_line: bool) -> Self {
+        self.own_line = own_line;
+        self
+    }
+}
+
+/// Preprocessor conditionals, like C's `#if 0 … #endif`, whose disabled branches count as comments.
+/// Each pattern is matched against whole lines, which must not begin within a comment or string.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConditionalConfig {
+    /// Regular expression matching the directives opening a disabled branch, like `#if 0`.
+    pub disabled: Pattern,
+
+    /// Regular expression matching the directives opening any other conditional, like `#ifdef`,
+    /// so that the `end` of a conditional nested within a disabled branch doesn't end that branch.
+    pub start: Pattern,
+
+    /// Regular expression matching the directives opening the alternative of a conditional, like `#else` or `#elif`,
+    /// which ends the disabled branch of that conditional. The alternative is itself disabled if it matches `disabled`, like `#elif 0`.
+    pub alternative: Pattern,
+
+    /// Regular expression matching the directives closing a conditional, like `#endif`.
+    pub end: Pattern,
+}
+
+impl ConditionalConfig {
+    /// Panics if any of the patterns is not a valid regular expression.
+    pub fn new(disabled: &str, start: &str, alternative: &str, end: &str) -> Self {
+        let pattern = |pattern| Pattern::new(pattern).expect("Invalid conditional directive");
+
+        Self {
+            disabled: pattern(disabled),
+            start: pattern(start),
+            alternative: pattern(alternative),
+            end: pattern(end),
+        }
+    }
+}
+
+/// A block of code to remove, like `#[cfg(test)] mod tests { … }` in Rust, which begins at its trigger
+/// and ends once the brackets opened after it are balanced. Brackets are only counted within code.
+/// If the block is `indented`, it instead lasts for as long as the lines are indented deeper than the trigger's.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StripBlockConfig {
+    /// Regular expression matching the beginning of the block, which should not contain its opening bracket.
+    pub trigger: Pattern,
+
+    /// The bracket opening the block, and any bracket nested within it; defaults to `{`.
+    #[serde(default = "default_block_open")]
+    pub open: String,
+
+    /// The bracket closing the block; defaults to `}`.
+    #[serde(default = "default_block_close")]
+    pub close: String,
+
+    /// Tokens which, found after the trigger but before the block opens, mean that no block follows,
+    /// like the `;` of `#[cfg(test)] use super::*;`. The lines that were already removed by then remain so.
+    #[serde(default)]
+    pub terminators: Vec<String>,
+
+    /// Whether the block is made of the lines following the trigger that are indented deeper than it, as in Python.
+    /// The header after the trigger lasts until the first `:` outside of brackets, and may span several lines.
+    /// Blank lines, lines starting with a comment and lines continuing a comment or string don't end the block.
+    /// Each whitespace character counts as one level of indentation.
+    #[serde(default)]
+    pub indented: bool,
+}
+
+fn default_block_open() -> String {
+    "{".to_string()
+}
+
+fn default_block_close() -> String {
+    "}".to_string()
+}
+
+impl StripBlockConfig {
+    /// Panics if `trigger` is not a valid regular expression.
+    pub fn new(trigger: &str) -> Self {
+        Self {
+            trigger: Pattern::new(trigger).expect("Invalid strip block trigger"),
+            open: default_block_open(),
+            close: default_block_close(),
+            terminators: Vec::new(),
+            indented: false,
+        }
+    }
+
+    pub fn brackets(mut self, open: &str, close: &str) -> Self {
+        self.open = open.to_string();
+        self.close = close.to_string();
+        self
+    }
+
+    pub fn terminator(mut self, terminator: &str) -> Self {
+        self.terminators.push(terminator.to_string());
+        self
+    }
+
+    pub fn indented(mut self, indented: bool) -> Self {
+        self.indented = indented;
+        self
+    }
+}
+
+/// An annotation, like `#[derive(Debug)]` in Rust or `@JsonProperty("foo")` in Java,
+/// which begins at its opener and ends once the brackets opened within or right after it are balanced.
+/// Brackets are only counted within code, so that those within the strings and comments of its arguments don't count.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AnnotationConfig {
+    /// Regular expression matching the opener, like `#!?\[` or `@\w+`.
+    pub start: Pattern,
+
+    /// The brackets enclosing the arguments of the annotation; defaults to `(` and `)`.
+    #[serde(default = "default_annotation_brackets")]
+    pub brackets: (String, String),
+}
+
+fn default_annotation_brackets() -> (String, String) {
+    ("(".to_string(), ")".to_string())
+}
+
+impl AnnotationConfig {
+    /// Panics if `start` is not a valid regular expression.
+    pub fn new(start: &str) -> Self {
+        Self {
+            start: Pattern::new(start).expect("Invalid annotation opener"),
+            brackets: default_annotation_brackets(),
+        }
+    }
+
+    pub fn brackets(mut self, open: &str, close: &str) -> Self {
+        self.brackets = (open.to_string(), close.to_string());
+        self
+    }
+}
+
+/// A section of the file delimited by marker comments, like `// region: vendored` … `// endregion`, which is removed as a whole.
+/// The markers are matched against the whole text of comments, including their opener, and sections may be nested.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HiddenSectionConfig {
+    /// Regular expression matching the comment opening the section.
+    pub start: Pattern,
+
+    /// Regular expression matching the comment closing the section.
+    pub end: Pattern,
+}
+
+impl HiddenSectionConfig {
+    /// Panics if `start` or `end` is not a valid regular expression.
+    pub fn new(start: &str, end: &str) -> Self {
+        Self {
+            start: Pattern::new(start).expect("Invalid hidden section start"),
+            end: Pattern::new(end).expect("Invalid hidden section end"),
+        }
+    }
+}
+
+/// The number of lines at the beginning and at the end of a file within which Vim modelines are looked for, as in Vim.
+const MODELINE_LINES: usize = 5;
+
+/// Returns the name of the interpreter of a shebang, like `python3` for `#!/usr/bin/env python3`.
+fn shebang_interpreter(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let program = words.next()?.rsplit('/').next()?;
+
+    if program == "env" {
+        // Skips the options and variable assignments of `env`, like `-S` or `FOO=bar`
+        words.find(|word| !word.starts_with('-') && !word.contains('='))
+    } else {
+        Some(program)
+    }
+}
+
+/// Returns the file type set by a Vim modeline, like `sh` for `# vim: set ft=sh:`.
+fn vim_filetype(line: &str) -> Option<&str> {
+    let options = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        line.match_indices(marker)
+            .find(|(index, _)| *index == 0 || line[..*index].ends_with(char::is_whitespace))
+            .map(|(index, _)| &line[index + marker.len()..])
+    })?;
+
+    options
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .find_map(|option| {
+            option
+                .strip_prefix("ft=")
+                .or_else(|| option.strip_prefix("filetype="))
+                .or_else(|| option.strip_prefix("syntax="))
+        })
+        .filter(|name| !name.is_empty())
+}
+
+/// Returns the mode set by an Emacs mode line, like `ruby` for `# -*- mode: ruby -*-` or `# -*- ruby -*-`.
+fn emacs_mode(line: &str) -> Option<&str> {
+    let start = line.find("-*-")? + 3;
+    let end = start + line[start..].find("-*-")?;
+    let variables = line[start..end].trim();
+
+    let mode = if variables.contains(':') {
+        variables.split(';').find_map(|variable| {
+            let (name, value) = variable.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("mode")
+                .then_some(value.trim())
+        })?
+    } else {
+        variables
+    };
+
+    (!mode.is_empty()).then_some(mode)
+}
+
+/// Deserializes a list whose items can either be tables, or shorthand strings.
+fn deserialize_shorthands<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + From<String>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Item<T> {
+        Shorthand(String),
+        Table(T),
+    }
+
+    let items = Vec::<Item<T>>::deserialize(deserializer)?;
+
+    Ok(items
+        .into_iter()
+        .map(|item| match item {
+            Item::Shorthand(shorthand) => T::from(shorthand),
+            Item::Table(table) => table,
+        })
+        .collect())
+}
+
+/// A kind of text to output instead of the code, see [`LangConfig::only`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Only {
+    Comments,
+    Strings,
+    Docs,
+}
+
+impl std::str::FromStr for Only {
+    type Err = String;
+
+    fn from_str(only: &str) -> Result<Self, String> {
+        match only {
+            "comments" => Ok(Self::Comments),
+            "strings" => Ok(Self::Strings),
+            "docs" => Ok(Self::Docs),
+            _ => Err(format!(
+                "expected `comments`, `strings` or `docs`, found `{}`",
+                only
+            )),
+        }
+    }
+}
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct LangConfig {
     /// A list of extensions that the language will match against; case-insensitive.
     pub extensions: Vec<String>,
 
+    /// The interpreters that a file's shebang may name for it to be of this language, like `python` for `#!/usr/bin/env python3`.
+    /// Version numbers following the interpreter's name are ignored.
+    #[serde(default)]
+    pub interpreters: Vec<String>,
+
+    /// The names of the language in Vim modelines and Emacs mode lines, like `sh` for `# vim: ft=sh`; case-insensitive.
+    #[serde(default)]
+    pub modeline_names: Vec<String>,
+
     /// What should count as the starter token for a line comment.
     /// The token itself as well as anything that follows it on that line will be ignored.
     pub line_comments: Vec<String>,
@@ -15,8 +501,84 @@ pub struct LangConfig {
     /// Pairs of multi-line comment openers/closers.
     pub multiline_comments: Vec<(String, String)>,
 
-    /// Pairs of string starters/enders.
-    pub strings: Vec<String>,
+    /// Tokens starting doc comments, like `///` or `/**`, which are kept when `keep_doc_comments` is set.
+    /// Those starting with a multi-line comment opener are closed like that comment, the others last until the end of the line.
+    #[serde(default)]
+    pub doc_comments: Vec<String>,
+
+    /// Comments whose text, following their opener on its line, matches one of these are kept, like `^\s*SAFETY:`.
+    /// A multi-line comment is kept as a whole.
+    #[serde(default)]
+    pub keep_comments_matching: Vec<Pattern>,
+
+    /// Regular expressions matching the markers of generated files, like `@generated`,
+    /// within the comments of their first lines. Such files are removed as a whole.
+    /// Prose languages, whose text isn't made of comments, are never considered generated.
+    #[serde(default)]
+    pub generated_markers: Vec<Pattern>,
+
+    /// Multi-line comments whose closer depends on their opener, like `--[==[ … ]==]` in Lua.
+    /// They cannot be nested, and blacklist tokens have no effect within them.
+    #[serde(default)]
+    pub raw_comments: Vec<RawDelimiter>,
+
+    /// String delimiters.
+    #[serde(deserialize_with = "deserialize_shorthands")]
+    pub strings: Vec<StringConfig>,
+
+    /// Delimiters of the strings that are docstrings when they stand alone on their line, like Python's `"""`.
+    /// Those strings are then doc comments rather than strings, and are removed even if `keep_strings` is set.
+    /// A string stands alone if only whitespace precedes its opener, and only whitespace or a line comment follows its closer;
+    /// the lines of a string spanning several lines are treated as a docstring until code is found after its closer.
+    #[serde(default)]
+    pub docstrings: Vec<String>,
+
+    /// The token escaping the character that follows it, like `\`; defaults to none.
+    /// It applies within strings that don't specify their own escape, and prevents escaped delimiters from opening strings.
+    #[serde(default)]
+    pub escape: Option<String>,
+
+    /// Delimiters of character literals, which only count as strings when they enclose a single (possibly escaped) character.
+    /// This lets `'a'` be a character literal, while `'a` remains part of the code, as is the case for lifetimes and labels in Rust.
+    #[serde(default)]
+    pub char_literals: Vec<String>,
+
+    /// Raw strings, whose closer depends on their opener, like `r#"…"#` in Rust.
+    /// Blacklist tokens have no effect within raw strings.
+    #[serde(default)]
+    pub raw_strings: Vec<RawDelimiter>,
+
+    /// Heredocs, whose terminator is captured by their opener.
+    #[serde(default)]
+    pub heredocs: Vec<HeredocConfig>,
+
+    /// Regions within which another language's rules apply, like `<script>` tags in HTML.
+    #[serde(default)]
+    pub regions: Vec<RegionConfig>,
+
+    /// Preprocessor conditionals whose disabled branches are removed like comments; the directives themselves are kept.
+    #[serde(default)]
+    pub conditionals: Option<ConditionalConfig>,
+
+    /// Blocks of code to remove, leaving blank lines in their place.
+    #[serde(default)]
+    pub strip_blocks: Vec<StripBlockConfig>,
+
+    /// Sections delimited by marker comments, which are removed along with everything they contain.
+    #[serde(default)]
+    pub hidden_sections: Vec<HiddenSectionConfig>,
+
+    /// Annotations, attributes and decorators, which are removed when `remove_annotations` is set.
+    #[serde(default)]
+    pub annotations: Vec<AnnotationConfig>,
+
+    /// Whether the text outside of regions is prose rather than code, in which case it is removed like comments.
+    #[serde(default)]
+    pub prose: bool,
+
+    /// The languages that `regions` may refer to; filled in by [`Config::resolve_regions`].
+    #[serde(skip)]
+    pub embedded_langs: IndexMap<String, LangConfig>,
 
     /// Tokens which should be excluded from participating in other tokens.
     #[serde(default)]
@@ -29,10 +591,67 @@ pub struct LangConfig {
     /// Whether or not to keep strings around; controlled by the global config.
     #[serde(skip)]
     pub keep_strings: bool,
+
+    /// Whether or not to keep doc comments around; controlled by the global config.
+    #[serde(skip)]
+    pub keep_doc_comments: bool,
+
+    /// Whether or not to remove annotations, along with their arguments; controlled by the global config.
+    #[serde(skip)]
+    pub remove_annotations: bool,
+
+    /// Whether to replace the removed text with spaces rather than deleting it; controlled by the command line.
+    #[serde(skip)]
+    pub mask: bool,
+
+    /// If set, only this kind of text is kept, and the code is removed instead; controlled by the command line.
+    #[serde(skip)]
+    pub only: Option<Only>,
 }
 
-#[cfg(test)]
+/// Builder methods, for constructing a `LangConfig` from code rather than from a config file.
 impl LangConfig {
+    /// Returns true if `filename`'s extension is one of the language's extensions.
+    pub fn matches_filename(&self, filename: &str) -> bool {
+        let Some(extension) = filename.split_terminator('.').next_back() else {
+            return false;
+        };
+        let extension = extension.to_lowercase();
+
+        self.extensions.iter().any(|ext| **ext == extension)
+    }
+
+    /// Returns true if `interpreter`, as named by a shebang, is one of the language's interpreters.
+    pub fn matches_interpreter(&self, interpreter: &str) -> bool {
+        let unversioned = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+
+        self.interpreters
+            .iter()
+            .any(|name| name == interpreter || name == unversioned)
+    }
+
+    /// Returns true if `name`, as found in a modeline, is one of the language's modeline names.
+    pub fn matches_modeline_name(&self, name: &str) -> bool {
+        self.modeline_names
+            .iter()
+            .any(|modeline_name| modeline_name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn extension(mut self, extension: &str) -> Self {
+        self.extensions.push(extension.to_lowercase());
+        self
+    }
+
+    pub fn interpreter(mut self, interpreter: &str) -> Self {
+        self.interpreters.push(interpreter.to_string());
+        self
+    }
+
+    pub fn modeline_name(mut self, name: &str) -> Self {
+        self.modeline_names.push(name.to_string());
+        self
+    }
+
     pub fn line_comment(mut self, comment: &str) -> Self {
         self.line_comments.push(comment.to_string());
         self
@@ -44,8 +663,94 @@ impl LangConfig {
         self
     }
 
-    pub fn string(mut self, delimiter: &str) -> Self {
-        self.strings.push(delimiter.to_string());
+    pub fn doc_comment(mut self, token: &str) -> Self {
+        self.doc_comments.push(token.to_string());
+        self
+    }
+
+    /// Panics if `pattern` is not a valid regular expression.
+    pub fn keep_comment_matching(mut self, pattern: &str) -> Self {
+        self.keep_comments_matching
+            .push(Pattern::new(pattern).expect("Invalid comment pattern"));
+        self
+    }
+
+    /// Panics if `pattern` is not a valid regular expression.
+    pub fn generated_marker(mut self, pattern: &str) -> Self {
+        self.generated_markers
+            .push(Pattern::new(pattern).expect("Invalid generated marker"));
+        self
+    }
+
+    /// Panics if `start` is not a valid regular expression.
+    pub fn raw_comment(mut self, start: &str, end: &str) -> Self {
+        self.raw_comments.push(RawDelimiter::new(start, end));
+        self
+    }
+
+    pub fn string(mut self, string: impl Into<StringConfig>) -> Self {
+        self.strings.push(string.into());
+        self
+    }
+
+    pub fn docstring(mut self, delimiter: &str) -> Self {
+        self.docstrings.push(delimiter.to_string());
+        self
+    }
+
+    pub fn escape(mut self, escape: &str) -> Self {
+        self.escape = Some(escape.to_string());
+        self
+    }
+
+    pub fn char_literal(mut self, delimiter: &str) -> Self {
+        self.char_literals.push(delimiter.to_string());
+        self
+    }
+
+    /// Panics if `start` is not a valid regular expression.
+    pub fn raw_string(mut self, start: &str, end: &str) -> Self {
+        self.raw_strings.push(RawDelimiter::new(start, end));
+        self
+    }
+
+    pub fn heredoc(mut self, heredoc: HeredocConfig) -> Self {
+        self.heredocs.push(heredoc);
+        self
+    }
+
+    pub fn region(mut self, region: RegionConfig) -> Self {
+        self.regions.push(region);
+        self
+    }
+
+    pub fn conditionals(mut self, conditionals: ConditionalConfig) -> Self {
+        self.conditionals = Some(conditionals);
+        self
+    }
+
+    pub fn strip_block(mut self, strip_block: StripBlockConfig) -> Self {
+        self.strip_blocks.push(strip_block);
+        self
+    }
+
+    pub fn hidden_section(mut self, hidden_section: HiddenSectionConfig) -> Self {
+        self.hidden_sections.push(hidden_section);
+        self
+    }
+
+    pub fn annotation(mut self, annotation: AnnotationConfig) -> Self {
+        self.annotations.push(annotation);
+        self
+    }
+
+    pub fn prose(mut self, prose: bool) -> Self {
+        self.prose = prose;
+        self
+    }
+
+    pub fn embedded_lang(mut self, name: &str, lang_config: LangConfig) -> Self {
+        self.embedded_langs.insert(name.to_string(), lang_config);
         self
     }
 
@@ -58,6 +763,31 @@ impl LangConfig {
         self.nested_comments = nested;
         self
     }
+
+    pub fn keep_strings(mut self, keep: bool) -> Self {
+        self.keep_strings = keep;
+        self
+    }
+
+    pub fn keep_doc_comments(mut self, keep: bool) -> Self {
+        self.keep_doc_comments = keep;
+        self
+    }
+
+    pub fn remove_annotations(mut self, remove: bool) -> Self {
+        self.remove_annotations = remove;
+        self
+    }
+
+    pub fn mask(mut self, mask: bool) -> Self {
+        self.mask = mask;
+        self
+    }
+
+    pub fn only(mut self, only: Option<Only>) -> Self {
+        self.only = only;
+        self
+    }
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
@@ -65,45 +795,312 @@ pub struct Config {
     #[serde(default)]
     pub keep_strings: bool,
 
+    #[serde(default)]
+    pub keep_doc_comments: bool,
+
+    #[serde(default)]
+    pub remove_annotations: bool,
+
+    /// Patterns of the comments to keep in every language, see [`LangConfig::keep_comments_matching`].
+    #[serde(default)]
+    pub keep_comments_matching: Vec<Pattern>,
+
+    /// Markers of generated files in every language, see [`LangConfig::generated_markers`].
+    #[serde(default)]
+    pub generated_markers: Vec<Pattern>,
+
+    /// Sections hidden in every language, see [`LangConfig::hidden_sections`].
+    #[serde(default)]
+    pub hidden_sections: Vec<HiddenSectionConfig>,
+
     #[serde(alias = "lang", default)]
     pub langs: IndexMap<String, LangConfig>,
 }
 
 impl Config {
+    /// The configuration that ships with `just-the-code`, for the languages supported out of the box.
+    pub fn builtin() -> Self {
+        toml::from_str(include_str!("./default_config.toml")).expect("Error parsing default config")
+    }
+
     /// Merges self with other, where the language configs of `other.langs` override those in `self`
     pub fn merge(mut self, other: Config) -> Self {
-        self.langs.extend(other.langs.into_iter());
+        self.langs.extend(other.langs);
+        self.keep_comments_matching
+            .extend(other.keep_comments_matching);
+        self.generated_markers.extend(other.generated_markers);
+        self.hidden_sections.extend(other.hidden_sections);
 
         Self {
             keep_strings: self.keep_strings || other.keep_strings,
+            keep_doc_comments: self.keep_doc_comments || other.keep_doc_comments,
+            remove_annotations: self.remove_annotations || other.remove_annotations,
+            keep_comments_matching: self.keep_comments_matching,
+            generated_markers: self.generated_markers,
+            hidden_sections: self.hidden_sections,
 
             langs: self.langs,
         }
     }
+
+    /// Parses the config file `custom` and merges it with self, see [`Config::merge`].
+    /// Unlike a new language, a language that self already defines only needs the fields to change,
+    /// which replace those of its existing definition.
+    pub fn merge_toml(self, custom: &str) -> Result<Self, toml::de::Error> {
+        let mut custom = toml::from_str::<toml::Table>(custom)?;
+
+        for key in ["lang", "langs"] {
+            let Some(toml::Value::Table(langs)) = custom.get_mut(key) else {
+                continue;
+            };
+            for (name, lang) in langs.iter_mut() {
+                let (Some(existing), toml::Value::Table(fields)) = (self.langs.get(name), lang)
+                else {
+                    continue;
+                };
+                let Ok(toml::Value::Table(mut merged)) = toml::Value::try_from(existing) else {
+                    unreachable!("Language configs are serialized as tables");
+                };
+                merged.extend(std::mem::take(fields));
+                *fields = merged;
+            }
+        }
+
+        Ok(self.merge(toml::Value::Table(custom).try_into()?))
+    }
+
+    /// Finds the language config whose extensions match `filename`.
+    /// Languages defined later (ie. in the custom config) take precedence over earlier ones.
+    pub fn lang_for_filename(&self, filename: &str) -> Option<&LangConfig> {
+        self.langs
+            .values()
+            .rev()
+            .find(|lang_config| lang_config.matches_filename(filename))
+    }
+
+    /// Finds the language config that the contents of a file name, for files whose extension doesn't tell their language:
+    /// through the interpreter of its shebang first, then through a Vim modeline within its first or last lines,
+    /// or an Emacs mode line on its first line (or second, after a shebang).
+    pub fn lang_for_contents(&self, contents: &str) -> Option<&LangConfig> {
+        self.lang_for_head_and_tail(contents, contents)
+    }
+
+    /// Like [`Config::lang_for_contents`], given only the beginning and the end of a file,
+    /// which may overlap, so that large files don't need to be read whole.
+    pub fn lang_for_head_and_tail(&self, head: &str, tail: &str) -> Option<&LangConfig> {
+        let find = |predicate: &dyn Fn(&LangConfig) -> bool| {
+            self.langs
+                .values()
+                .rev()
+                .find(|lang_config| predicate(lang_config))
+        };
+
+        let shebang = head.lines().next().and_then(shebang_interpreter);
+        if let Some(found) =
+            shebang.and_then(|interpreter| find(&|lang| lang.matches_interpreter(interpreter)))
+        {
+            return Some(found);
+        }
+
+        let emacs_lines = if shebang.is_some() { 2 } else { 1 };
+        head.lines()
+            .take(emacs_lines)
+            .filter_map(emacs_mode)
+            .chain(
+                head.lines()
+                    .take(MODELINE_LINES)
+                    .chain(tail.lines().rev().take(MODELINE_LINES))
+                    .filter_map(vim_filetype),
+            )
+            .find_map(|name| find(&|lang| lang.matches_modeline_name(name)))
+    }
+
+    /// Fills in the languages that `lang_config`'s regions refer to; every language may be needed
+    /// if they depend on what their opener matched. The embedded languages also keep the comments that `lang_config` keeps.
+    /// Regions within the embedded languages themselves are left unresolved.
+    pub fn resolve_regions(&self, lang_config: &mut LangConfig) {
+        for region in lang_config.regions.iter() {
+            let names = if region.lang.contains('$') {
+                self.langs.keys().collect::<Vec<_>>()
+            } else {
+                self.langs
+                    .get_key_value(&region.lang)
+                    .map(|(name, _)| name)
+                    .into_iter()
+                    .collect()
+            };
+
+            for name in names {
+                let mut embedded = self.langs[name].clone();
+                embedded.keep_strings = lang_config.keep_strings;
+                embedded
+                    .keep_comments_matching
+                    .extend(lang_config.keep_comments_matching.iter().cloned());
+                lang_config.embedded_langs.insert(name.clone(), embedded);
+            }
+        }
+    }
 }
 
-#[derive(Options)]
-pub struct RuntimeConfig {
-    #[options(free)]
-    pub filename: Option<String>,
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_builtin_config() {
+        let config = Config::builtin();
 
-    pub help: bool,
+        assert!(config.lang_for_filename("main.rs").is_some());
+        assert!(config.lang_for_filename("MAIN.PY").is_some());
+        assert!(config.lang_for_filename("README").is_none());
+    }
 
-    #[options(help = "When set, the file will be read from stdin instead.")]
-    pub read_stdin: bool,
+    #[test]
+    fn test_lang_for_contents() {
+        let config = Config::builtin();
+        let lang_for_contents = |contents: &str| {
+            config
+                .lang_for_contents(contents)
+                .and_then(|lang_config| lang_config.extensions.first().cloned())
+        };
 
-    #[options(help = "When set, overrides which language to interpret the file as.")]
-    pub language: Option<String>,
+        assert_eq!(
+            lang_for_contents("#!/usr/bin/env python3\n"),
+            Some("py".to_string())
+        );
+        assert_eq!(
+            lang_for_contents("#!/usr/bin/env -S ruby -w\n"),
+            Some("rb".to_string())
+        );
+        assert_eq!(
+            lang_for_contents("#!/bin/bash -e\necho"),
+            Some("sh".to_string())
+        );
+        assert_eq!(
+            lang_for_contents("#!/bin/sh\n# -*- mode: ruby -*-\n"),
+            Some("sh".to_string())
+        );
+        assert_eq!(
+            lang_for_contents("#!/usr/bin/foo\n# -*- mode: ruby -*-\n"),
+            Some("rb".to_string())
+        );
+        assert_eq!(
+            lang_for_contents("// -*- C++ -*-\nint a;"),
+            Some("cpp".to_string())
+        );
+        assert_eq!(
+            lang_for_contents("a\nb\n# vim: set ts=4 ft=perl:\n"),
+            Some("pl".to_string())
+        );
+        assert_eq!(
+            lang_for_contents("-- vi: filetype=lua\n"),
+            Some("lua".to_string())
+        );
+        assert_eq!(lang_for_contents("a\n".repeat(20).as_str()), None);
+        assert_eq!(lang_for_contents("avim: ft=sh\n"), None);
+        assert_eq!(lang_for_contents("#!/usr/bin/env unknown\n"), None);
 
-    #[options(
-        short = "s",
-        help = "When set, strings will be kept in the output file, ignoring the behaviour specified by the config file."
-    )]
-    pub keep_strings: bool,
+        let lang_for_head_and_tail = |head: &str, tail: &str| {
+            config
+                .lang_for_head_and_tail(head, tail)
+                .and_then(|lang_config| lang_config.extensions.first().cloned())
+        };
+        assert_eq!(
+            lang_for_head_and_tail("a\nb\n", "y\n# vim: ft=sh\nz\n"),
+            Some("sh".to_string())
+        );
+        assert_eq!(
+            lang_for_head_and_tail("a\n", "# vim: ft=sh\nz\nz\nz\nz\nz\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_merge_toml() {
+        let config = Config::builtin()
+            .merge_toml(
+                r##"
+                keep_strings = true
+
+                [[lang.rust.strip_blocks]]
+                trigger = '#\[cfg\(test\)\]'
+                terminators = [";"]
+
+                [lang.python]
+                line_comments = ["#", "//"]
+
+                [lang.toy]
+                extensions = ["toy"]
+                line_comments = [";"]
+                multiline_comments = []
+                strings = []
+                "##,
+            )
+            .unwrap();
 
-    #[options(
-        short = "S",
-        help = "When set, strings will be removed from the output file, ignoring the behaviour specified by the config file."
-    )]
-    pub remove_strings: bool,
+        let rust = config.lang_for_filename("main.rs").unwrap();
+        assert_eq!(rust.strip_blocks.len(), 1);
+        assert_eq!(rust.line_comments, ["//"]);
+        assert_eq!(rust.raw_strings.len(), 1);
+        let python = config.lang_for_filename("main.py").unwrap();
+        assert_eq!(python.line_comments, ["#", "//"]);
+        assert_eq!(python.docstrings.len(), 2);
+        assert!(config.lang_for_filename("main.toy").is_some());
+        assert!(config.keep_strings);
+
+        assert!(Config::builtin()
+            .merge_toml("[lang.toy]\nextensions = [\"toy\"]")
+            .is_err());
+
+        // Every built-in language survives being serialized and parsed back
+        let builtin = Config::builtin();
+        let names = builtin.langs.keys().cloned().collect::<Vec<_>>();
+        let custom = names
+            .iter()
+            .map(|name| format!("[lang.{}]\n", name))
+            .collect::<String>();
+        let merged = Config::builtin().merge_toml(&custom).unwrap();
+        for name in names {
+            assert_eq!(
+                format!("{:?}", merged.langs[&name]),
+                format!("{:?}", builtin.langs[&name])
+            );
+        }
+    }
+
+    #[test]
+    fn test_resolve_regions() {
+        let config = Config::builtin();
+
+        let mut html = config.lang_for_filename("index.html").unwrap().clone();
+        config.resolve_regions(&mut html);
+        assert!(html.embedded_langs.contains_key("js_like"));
+        assert!(html.embedded_langs.contains_key("css"));
+        assert!(!html.embedded_langs.contains_key("rust"));
+
+        let mut markdown = config.lang_for_filename("README.md").unwrap().clone();
+        config.resolve_regions(&mut markdown);
+        assert_eq!(markdown.embedded_langs.len(), config.langs.len());
+    }
+
+    #[test]
+    fn test_string_shorthands() {
+        let config: LangConfig = toml::from_str(
+            r#"
+            extensions = []
+            line_comments = []
+            multiline_comments = []
+            strings = ["'", { delimiter = "`", interpolation = ["${", "}"] }]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.strings[0].delimiter, "'");
+        assert_eq!(config.strings[0].interpolation, None);
+        assert_eq!(config.strings[1].delimiter, "`");
+        assert_eq!(
+            config.strings[1].interpolation,
+            Some(("${".to_string(), "}".to_string()))
+        );
+    }
 }
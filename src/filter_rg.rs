@@ -0,0 +1,247 @@
+//! Filters the output of `rg --json`, dropping the matches that only lie within comments or strings.
+//! Unlike `rg --pre just-the-code`, this lets ripgrep print the original lines of code.
+
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::*;
+
+#[derive(Deserialize)]
+struct Message {
+    #[serde(rename = "type")]
+    kind: String,
+    data: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct MatchData {
+    path: Data,
+    lines: Data,
+    line_number: Option<usize>,
+    submatches: Vec<SubMatch>,
+}
+
+/// Ripgrep encodes non-UTF-8 data as `{"bytes": "<base64>"}`; such data is left untouched.
+#[derive(Deserialize)]
+struct Data {
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SubMatch {
+    start: usize,
+    end: usize,
+}
+
+/// The parsed comments and strings of the last file that had a match.
+struct ParsedFile {
+    path: String,
+    lang_config: Option<LangConfig>,
+    lines: Option<Vec<LineRanges>>,
+}
+
+/// Reads ripgrep's JSON Lines stream from `input` and writes it back to `output`,
+/// omitting the `match` events whose submatches are all within comments or strings.
+pub fn filter_rg(
+    config: &Config,
+    runtime_config: &RuntimeConfig,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> std::io::Result<()> {
+    let mut parsed_file: Option<ParsedFile> = None;
+
+    for line in input.lines() {
+        let line = line?;
+
+        if keep_message(config, runtime_config, &line, &mut parsed_file) {
+            output.write_all(line.as_bytes())?;
+            output.write_all(b"\n")?;
+        }
+    }
+
+    Ok(())
+}
+
+fn keep_message(
+    config: &Config,
+    runtime_config: &RuntimeConfig,
+    line: &str,
+    parsed_file: &mut Option<ParsedFile>,
+) -> bool {
+    let Ok(message) = serde_json::from_str::<Message>(line) else {
+        return true;
+    };
+    if message.kind != "match" {
+        return true;
+    }
+    let Ok(data) = serde_json::from_value::<MatchData>(message.data) else {
+        return true;
+    };
+    let (Some(path), Some(text), Some(line_number)) =
+        (data.path.text, data.lines.text, data.line_number)
+    else {
+        return true;
+    };
+
+    if parsed_file
+        .as_ref()
+        .is_none_or(|parsed| parsed.path != path)
+    {
+        *parsed_file = Some(parse_file(config, runtime_config, path));
+    }
+    let parsed_file = parsed_file.as_ref().unwrap();
+    let (Some(lang_config), Some(lines)) = (&parsed_file.lang_config, &parsed_file.lines) else {
+        return true;
+    };
+
+    data.submatches.is_empty()
+        || data.submatches.iter().any(|submatch| {
+            is_code(
+                lines,
+                line_number.saturating_sub(1),
+                &text,
+                submatch,
+                lang_config,
+            )
+        })
+}
+
+fn parse_file(config: &Config, runtime_config: &RuntimeConfig, path: String) -> ParsedFile {
+    let lang_config = Path::new(&path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| config.lang_for_filename(name))
+        .cloned()
+        .map(|mut lang_config| {
+            copy_config(&mut lang_config, config, runtime_config);
+            lang_config
+        })
+        .or_else(|| {
+            let contents = std::fs::read(&path).ok()?;
+            detect_lang_config(config, runtime_config, &contents, &contents)
+        });
+
+    let lines = lang_config.as_ref().and_then(|lang_config| {
+        let file = std::fs::File::open(&path).ok()?;
+        parse_input(lang_config, BufReader::new(file)).ok()
+    });
+
+    ParsedFile {
+        path,
+        lang_config,
+        lines,
+    }
+}
+
+/// Returns true if any byte of `submatch` would be kept by `just-the-code`.
+/// `text` may span several lines, the first of which is `lines[first_line]`.
+fn is_code(
+    lines: &[LineRanges],
+    first_line: usize,
+    text: &str,
+    submatch: &SubMatch,
+    lang_config: &LangConfig,
+) -> bool {
+    let mut line_index = first_line;
+    let mut line_start = 0;
+
+    for (index, byte) in text
+        .bytes()
+        .enumerate()
+        .take(submatch.end.max(submatch.start + 1))
+    {
+        if byte == b'\n' {
+            line_index += 1;
+            line_start = index + 1;
+            continue;
+        }
+        if index < submatch.start || byte == b'\r' {
+            continue;
+        }
+
+        let Some(line_ranges) = lines.get(line_index) else {
+            // The file changed since ripgrep read it
+            return true;
+        };
+        if !line_ranges.is_stripped(index - line_start, lang_config) {
+            return true;
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use gumdrop::Options;
+    use serde_json::json;
+
+    use super::*;
+
+    fn match_message(
+        path: &str,
+        lines: &str,
+        line_number: usize,
+        start: usize,
+        end: usize,
+    ) -> String {
+        json!({
+            "type": "match",
+            "data": {
+                "path": { "text": path },
+                "lines": { "text": lines },
+                "line_number": line_number,
+                "absolute_offset": 0,
+                "submatches": [{ "match": { "text": &lines[start..end] }, "start": start, "end": end }],
+            },
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_filter_rg() {
+        let path =
+            std::env::temp_dir().join(format!("just-the-code-filter-rg-{}.rs", std::process::id()));
+        std::fs::write(
+            &path,
+            "let needle = 1; // needle\nlet s = \"needle\";\nf(a,\n    b); // c\n/* x\ny */ z\n",
+        )
+        .unwrap();
+        let path_str = path.to_str().unwrap();
+
+        let begin =
+            json!({ "type": "begin", "data": { "path": { "text": path_str } } }).to_string();
+        let in_code = match_message(path_str, "let needle = 1; // needle\n", 1, 4, 10);
+        let in_comment = match_message(path_str, "let needle = 1; // needle\n", 1, 19, 25);
+        let in_string = match_message(path_str, "let s = \"needle\";\n", 2, 9, 15);
+        let multiline_code = match_message(path_str, "f(a,\n    b); // c\n", 3, 2, 10);
+        let multiline_comment = match_message(path_str, "/* x\ny */ z\n", 5, 3, 6);
+        let end = json!({ "type": "end", "data": { "path": { "text": path_str } } }).to_string();
+
+        let input = [
+            &begin,
+            &in_code,
+            &in_comment,
+            &in_string,
+            &multiline_code,
+            &multiline_comment,
+            &end,
+        ]
+        .map(|message| format!("{}\n", message))
+        .concat();
+        let expected = [&begin, &in_code, &multiline_code, &end]
+            .map(|message| format!("{}\n", message))
+            .concat();
+
+        let config = Config::builtin();
+        let runtime_config = RuntimeConfig::parse_args_default::<&str>(&[]).unwrap();
+        let mut output = Vec::new();
+        let result = filter_rg(&config, &runtime_config, input.as_bytes(), &mut output);
+        std::fs::remove_file(&path).unwrap();
+
+        result.unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+}
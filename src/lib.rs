@@ -0,0 +1,34 @@
+//! Strips comments and strings from source code, without needing to build a syntax tree.
+//!
+//! Languages are described by a [`LangConfig`], which only needs to list how comments and strings look like.
+//! It is then compiled into a [`Stripper`], which can either write the stripped code,
+//! or split a text into [`Segment`]s of code, comments and strings:
+//!
+//! ```
+//! use just_the_code::{LangConfig, SegmentKind, Stripper};
+//!
+//! let stripper = Stripper::new(LangConfig::default().line_comment("//").string("\""));
+//! let text = "let a = \"b\"; // c\n";
+//!
+//! let kinds = stripper
+//!     .segments(text)
+//!     .map(|segment| (segment.kind, &text[segment.byte_range]))
+//!     .collect::<Vec<_>>();
+//!
+//! assert_eq!(
+//!     kinds,
+//!     [
+//!         (SegmentKind::Code, "let a = "),
+//!         (SegmentKind::String, "\"b\""),
+//!         (SegmentKind::Code, "; "),
+//!         (SegmentKind::Comment, "// c"),
+//!         (SegmentKind::Code, "\n"),
+//!     ]
+//! );
+//! ```
+
+pub mod config;
+pub use config::*;
+
+pub mod parse;
+pub use parse::*;
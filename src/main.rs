@@ -1,16 +1,18 @@
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
 
 use gumdrop::Options;
 
-mod config;
-use config::*;
+use just_the_code::*;
 
-mod parse;
-use parse::*;
+mod runtime_config;
+use runtime_config::*;
+
+mod filter_rg;
+mod notebook;
+mod search;
 
 fn load_config() -> Config {
-    let default_config: Config = toml::from_str(include_str!("./default_config.toml"))
-        .expect("Error parsing default config");
+    let default_config = Config::builtin();
 
     let Some(custom_config) =
         directories::ProjectDirs::from("xyz", "Shad Amethyst", "just-the-code")
@@ -24,31 +26,104 @@ fn load_config() -> Config {
         return default_config;
     };
 
-    let custom_config = match toml::from_str(&custom_config) {
+    match default_config.merge_toml(&custom_config) {
         Ok(config) => config,
         Err(error) => {
-            panic!("Error parsing custom config: {}", error);
+            eprintln!(
+                "Error parsing custom config, using the built-in one instead: {}",
+                error
+            );
+            Config::builtin()
         }
-    };
-
-    default_config.merge(custom_config)
+    }
 }
 
 fn main() {
     let config = load_config();
     let runtime_config = RuntimeConfig::parse_args_default_or_exit();
 
-    let Some(input_stream) = get_input_stream(&runtime_config) else {
+    // A lone argument naming an existing file is processed as such,
+    // so that `rg --pre` still works on files named like a subcommand
+    let subcommand = match runtime_config.args.as_slice() {
+        [file] if std::path::Path::new(file).is_file() => None,
+        _ => runtime_config.filename(),
+    };
+
+    match subcommand {
+        Some("search") => {
+            let found = search::search(&config, &runtime_config, &runtime_config.args[1..]);
+            std::process::exit(if found { 0 } else { 1 });
+        }
+        Some("filter-rg") => {
+            let result = filter_rg::filter_rg(
+                &config,
+                &runtime_config,
+                std::io::stdin().lock(),
+                std::io::stdout().lock(),
+            );
+            exit_on_error(result, "Error filtering ripgrep output");
+            return;
+        }
+        _ => {}
+    }
+
+    if runtime_config.args.len() > 1 {
+        eprintln!(
+            "Only one input file can be specified. Run `{} --help` for more information.",
+            program_name()
+        );
+        std::process::exit(2);
+    }
+
+    let Some(mut input_stream) = get_input_stream(&runtime_config) else {
         eprintln!(
             "No input file specified, or --read-stdin not given. Run `{} --help` for more information.",
-            std::env::args().nth(0).unwrap()
+            program_name()
         );
         return;
     };
 
-    match get_lang_config(config, &runtime_config) {
+    if runtime_config.filename().is_some_and(notebook::is_notebook)
+        || runtime_config.language.as_deref() == Some("ipynb")
+    {
+        let result = notebook::handle_notebook(
+            &config,
+            &runtime_config,
+            input_stream,
+            std::io::stdout().lock(),
+        );
+        exit_on_error(result, "Error processing notebook");
+        return;
+    }
+
+    if let Some(lang_config) = get_lang_config(&config, &runtime_config) {
+        let result = handle_input(&lang_config, input_stream, std::io::stdout().lock());
+        exit_on_error(result, "Error processing input");
+        return;
+    }
+
+    // The file may still tell its language in its first or last lines; only those are read,
+    // and the input is then streamed from its beginning
+    let mut head = Vec::new();
+    exit_on_error(
+        input_stream
+            .by_ref()
+            .take(SNIFFED_BYTES as u64)
+            .read_to_end(&mut head),
+        "Error reading input",
+    );
+    let tail = match runtime_config.filename() {
+        Some(filename) if head.len() == SNIFFED_BYTES => {
+            exit_on_error(read_tail(filename), "Error reading input")
+        }
+        _ => head.clone(),
+    };
+    let input_stream = head.as_slice().chain(input_stream);
+
+    match detect_lang_config(&config, &runtime_config, &head, &tail) {
         Some(lang_config) => {
-            handle_input(lang_config, input_stream, std::io::stdout().lock());
+            let result = handle_input(&lang_config, input_stream, std::io::stdout().lock());
+            exit_on_error(result, "Error processing input");
         }
         None => {
             noop(input_stream);
@@ -56,29 +131,58 @@ fn main() {
     }
 }
 
-/// If the file does not need to be processed, then we simply pipe it through
-fn noop(mut input: impl BufRead) {
-    use std::io::*;
+fn program_name() -> String {
+    std::env::args()
+        .next()
+        .unwrap_or("just-the-code".to_string())
+}
 
-    let mut stdout = stdout().lock();
-    // When ripgrep isn't interested anymore in what we're outputting, it may choose to close the pipe before we're finished writing to it,
-    // so we have to handle that case and gracefully shut down:
-    match copy(&mut input, &mut stdout) {
-        Ok(_) => {}
+/// When ripgrep isn't interested anymore in what we're outputting, it may choose to close the pipe before we're finished writing to it,
+/// so we have to handle that case and gracefully shut down.
+fn exit_on_error<T>(result: std::io::Result<T>, context: &str) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => std::process::exit(0),
         Err(err) => {
-            if err.kind() != ErrorKind::BrokenPipe {
-                eprintln!("Error piping stdin to stdout: {:?}", err);
-            }
+            eprintln!("{}: {:?}", context, err);
+            std::process::exit(1);
         }
     }
+}
+
+/// How much of each end of a file of unknown language is read to find its shebang or modelines.
+const SNIFFED_BYTES: usize = 8 * 1024;
+
+/// Reads the last [`SNIFFED_BYTES`] of `filename` at most, starting at the beginning of a line.
+fn read_tail(filename: &str) -> std::io::Result<Vec<u8>> {
+    let mut file = std::fs::File::open(filename)?;
+    let start = file.metadata()?.len().saturating_sub(SNIFFED_BYTES as u64);
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    if start > 0 {
+        let line_start = tail
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(tail.len(), |index| index + 1);
+        tail.drain(..line_start);
+    }
+
+    Ok(tail)
+}
+
+/// If the file does not need to be processed, then we simply pipe it through
+fn noop(mut input: impl BufRead) {
+    let result = std::io::copy(&mut input, &mut std::io::stdout().lock());
+    exit_on_error(result, "Error piping stdin to stdout");
 
     std::process::exit(0);
 }
 
 #[inline]
 fn get_input_stream(runtime_config: &RuntimeConfig) -> Option<Box<dyn BufRead>> {
-    if runtime_config.filename.is_some() {
-        let filename = runtime_config.filename.clone().unwrap();
+    if let Some(filename) = runtime_config.filename() {
         Some(Box::new(BufReader::new(
             std::fs::File::open(filename).expect("Couldn't open specified file"),
         )))
@@ -97,31 +201,75 @@ fn copy_config(lang_config: &mut LangConfig, config: &Config, runtime_config: &R
     } else {
         lang_config.keep_strings = config.keep_strings;
     }
-}
 
-fn get_lang_config(mut config: Config, runtime_config: &RuntimeConfig) -> Option<LangConfig> {
-    if let Some(lang) = &runtime_config.language {
-        if let Some(mut lang_config) = config.langs.get(lang).cloned() {
-            copy_config(&mut lang_config, &config, &runtime_config);
-            return Some(lang_config);
-        }
+    if runtime_config.keep_doc_comments {
+        lang_config.keep_doc_comments = true;
+    } else if runtime_config.remove_doc_comments {
+        lang_config.keep_doc_comments = false;
+    } else {
+        lang_config.keep_doc_comments = config.keep_doc_comments;
     }
 
-    let Some(filename) = &runtime_config.filename else {
-        return None;
-    };
+    if runtime_config.keep_annotations {
+        lang_config.remove_annotations = false;
+    } else if runtime_config.remove_annotations {
+        lang_config.remove_annotations = true;
+    } else {
+        lang_config.remove_annotations = config.remove_annotations;
+    }
 
-    let extension = filename
-        .split_terminator(".")
-        .last()
-        .unwrap()
-        .to_lowercase();
-    for mut lang_config in std::mem::take(&mut config.langs).into_values().rev() {
-        if lang_config.extensions.iter().any(|ext| **ext == extension) {
-            copy_config(&mut lang_config, &config, &runtime_config);
-            return Some(lang_config);
-        }
+    lang_config
+        .keep_comments_matching
+        .extend(config.keep_comments_matching.iter().cloned());
+    lang_config
+        .keep_comments_matching
+        .extend(runtime_config.keep_comment.iter().cloned());
+
+    lang_config
+        .hidden_sections
+        .extend(config.hidden_sections.iter().cloned());
+
+    if runtime_config.keep_generated {
+        lang_config.generated_markers.clear();
+    } else {
+        lang_config
+            .generated_markers
+            .extend(config.generated_markers.iter().cloned());
     }
 
-    None
+    lang_config.mask = runtime_config.mask;
+    lang_config.only = runtime_config.only;
+
+    config.resolve_regions(lang_config);
+}
+
+fn get_lang_config(config: &Config, runtime_config: &RuntimeConfig) -> Option<LangConfig> {
+    let mut lang_config = match &runtime_config.language {
+        Some(lang) if config.langs.contains_key(lang) => config.langs[lang].clone(),
+        _ => config
+            .lang_for_filename(runtime_config.filename()?)?
+            .clone(),
+    };
+
+    copy_config(&mut lang_config, config, runtime_config);
+    Some(lang_config)
+}
+
+/// Finds the language config of a file from its `head` and `tail`, which may overlap or both be the whole file,
+/// through its shebang or modelines, see [`Config::lang_for_head_and_tail`].
+fn detect_lang_config(
+    config: &Config,
+    runtime_config: &RuntimeConfig,
+    head: &[u8],
+    tail: &[u8],
+) -> Option<LangConfig> {
+    let mut lang_config = config
+        .lang_for_head_and_tail(
+            &String::from_utf8_lossy(head),
+            &String::from_utf8_lossy(tail),
+        )?
+        .clone();
+
+    copy_config(&mut lang_config, config, runtime_config);
+    Some(lang_config)
 }
@@ -0,0 +1,203 @@
+//! Support for Jupyter notebooks, which are JSON files: only the source of their code cells is kept,
+//! and stripped according to the language of their kernel.
+
+use std::io::{BufRead, Write};
+
+use serde::Deserialize;
+
+use crate::*;
+
+/// Written between consecutive code cells, as in the percent format of jupytext.
+const CELL_SEPARATOR: &str = "# %%";
+
+#[derive(Deserialize)]
+struct Notebook {
+    cells: Vec<Cell>,
+    #[serde(default)]
+    metadata: Metadata,
+}
+
+#[derive(Default, Deserialize)]
+struct Metadata {
+    kernelspec: Option<KernelSpec>,
+    language_info: Option<LanguageInfo>,
+}
+
+#[derive(Deserialize)]
+struct KernelSpec {
+    language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LanguageInfo {
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Cell {
+    cell_type: String,
+    #[serde(default)]
+    source: Source,
+}
+
+/// The source of a cell is usually split into lines, each keeping its line terminator.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Source {
+    Lines(Vec<String>),
+    Text(String),
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Self::Text(String::new())
+    }
+}
+
+impl Source {
+    fn to_text(&self) -> String {
+        match self {
+            Source::Lines(lines) => lines.concat(),
+            Source::Text(text) => text.clone(),
+        }
+    }
+}
+
+/// Returns true if `filename` looks like a Jupyter notebook.
+pub fn is_notebook(filename: &str) -> bool {
+    filename.to_lowercase().ends_with(".ipynb")
+}
+
+/// Writes the code cells of the notebook read from `input` to `output`, separated by [`CELL_SEPARATOR`].
+/// Their comments and strings are stripped if the language of the notebook's kernel is known.
+/// Input that isn't a valid notebook is written as-is.
+pub fn handle_notebook(
+    config: &Config,
+    runtime_config: &RuntimeConfig,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> std::io::Result<()> {
+    let mut contents = Vec::new();
+    input.read_to_end(&mut contents)?;
+    let Ok(notebook) = serde_json::from_slice::<Notebook>(&contents) else {
+        // Like other input that can't be processed, an invalid notebook is passed through unchanged
+        return output.write_all(&contents);
+    };
+
+    let stripper = kernel_language(&notebook.metadata)
+        .and_then(|language| {
+            config
+                .langs
+                .get(language)
+                .or_else(|| config.lang_for_filename(&format!(".{}", language)))
+        })
+        .map(|lang_config| {
+            let mut lang_config = lang_config.clone();
+            copy_config(&mut lang_config, config, runtime_config);
+            Stripper::new(lang_config)
+        });
+
+    let code_cells = notebook
+        .cells
+        .iter()
+        .filter(|cell| cell.cell_type == "code");
+
+    for (index, cell) in code_cells.enumerate() {
+        if index > 0 {
+            writeln!(output, "{}", CELL_SEPARATOR)?;
+        }
+
+        let source = cell.source.to_text();
+        match &stripper {
+            Some(stripper) => stripper.strip(source.as_bytes(), &mut output)?,
+            None => {
+                for line in source.lines() {
+                    writeln!(output, "{}", line)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn kernel_language(metadata: &Metadata) -> Option<&str> {
+    metadata
+        .kernelspec
+        .as_ref()
+        .and_then(|kernelspec| kernelspec.language.as_deref())
+        .or_else(|| {
+            metadata
+                .language_info
+                .as_ref()
+                .and_then(|language_info| language_info.name.as_deref())
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use gumdrop::Options;
+    use serde_json::json;
+
+    use super::*;
+
+    fn test_notebook(notebook: serde_json::Value, expected: &str) {
+        let config = Config::builtin();
+        let runtime_config = RuntimeConfig::parse_args_default::<&str>(&[]).unwrap();
+        let mut output = Vec::new();
+        handle_notebook(
+            &config,
+            &runtime_config,
+            notebook.to_string().as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_handle_notebook() {
+        let cells = json!([
+            { "cell_type": "markdown", "source": ["# Title # a\n", "Text"] },
+            {
+                "cell_type": "code",
+                "source": ["x = 'a' # b\n", "y = 1\n"],
+                "outputs": [{ "output_type": "stream", "text": ["# c\n"] }],
+            },
+            { "cell_type": "raw", "source": "raw # d" },
+            { "cell_type": "code", "source": "z = 2 # e", "outputs": [] },
+        ]);
+
+        test_notebook(
+            json!({
+                "cells": cells,
+                "metadata": {
+                    "kernelspec": { "language": "python" },
+                    "language_info": { "name": "ruby" },
+                },
+            }),
+            "x = \"\"\"…\"\"\" \ny = 1\n# %%\nz = 2 \n",
+        );
+        test_notebook(
+            json!({
+                "cells": cells,
+                "metadata": {
+                    "kernelspec": { "display_name": "Python 3" },
+                    "language_info": { "name": "python" },
+                },
+            }),
+            "x = \"\"\"…\"\"\" \ny = 1\n# %%\nz = 2 \n",
+        );
+        test_notebook(
+            json!({ "cells": cells, "metadata": {} }),
+            "x = 'a' # b\ny = 1\n# %%\nz = 2 # e\n",
+        );
+
+        let truncated = r#"{"cells": [{"cell_type": "code", "source": ["x = 1 # a\n"#;
+        let config = Config::builtin();
+        let runtime_config = RuntimeConfig::parse_args_default::<&str>(&[]).unwrap();
+        let mut output = Vec::new();
+        handle_notebook(&config, &runtime_config, truncated.as_bytes(), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), truncated);
+    }
+}
@@ -1,4 +1,7 @@
-use std::io::{BufRead, IoSlice, Write};
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+
+use indexmap::IndexMap;
 
 use crate::*;
 
@@ -40,7 +43,7 @@ impl Range {
         if self.start == self.end || other.start == other.end {
             false
         } else {
-            self.start + 1 <= other.end && other.start + 1 <= self.end
+            self.start < other.end && other.start < self.end
         }
     }
 }
@@ -82,16 +85,27 @@ impl Ranges {
         }
     }
 
-    fn remove(&self, negative: &mut NegativeRange) {
-        for closed in self.closed_ranges.iter() {
-            closed.remove(negative);
-        }
-        for start in self.open_ranges.iter() {
-            negative.remove(*start, usize::MAX);
-        }
+    /// Adds a range which both opens and closes on the current line.
+    fn push(&mut self, range: Range) {
+        self.closed_ranges.push(range);
+    }
+
+    /// Returns all of the ranges, with the open ones extending past `line_end`.
+    fn to_vec(&self, line_end: usize) -> Vec<Range> {
+        self.closed_ranges
+            .iter()
+            .copied()
+            .chain(
+                self.open_ranges
+                    .iter()
+                    .map(|start| Range::new(*start, line_end + 1)),
+            )
+            .filter(|range| range.start != range.end)
+            .collect()
     }
 }
 
+/// The parts of a line that remain once others are removed from it.
 #[derive(Debug)]
 struct NegativeRange {
     ranges: Vec<Range>,
@@ -105,9 +119,6 @@ impl NegativeRange {
     }
 
     fn remove(&mut self, start: usize, end: usize) {
-        let neg_start = start.saturating_sub(1);
-        let neg_end = end;
-
         self.ranges = self
             .ranges
             .drain(..)
@@ -115,24 +126,25 @@ impl NegativeRange {
                 let mut lhs = None;
                 let mut rhs = None;
 
-                if neg_start > range.start {
+                if start > range.start {
                     // The input doesn't begin at the lhs of the range:
                     // range : [=====...
                     // input :    [==...
                     // lhs   : [=]
-                    lhs = Some(Range::new(range.start, neg_start.min(range.end)));
+                    lhs = Some(Range::new(range.start, start.min(range.end)));
                 }
-                if range.end > neg_end {
+                if range.end > end {
                     // The input doesn't end at the rhs of the range:
                     // range : ...=====]
                     // input : ...==]
                     // rhs   :       [=]
-                    rhs = Some(Range::new(neg_end.max(range.start), range.end));
+                    rhs = Some(Range::new(end.max(range.start), range.end));
                 }
 
                 [lhs, rhs]
             })
             .flatten()
+            .filter(|range| range.start != range.end)
             .collect();
     }
 }
@@ -142,425 +154,2707 @@ enum TokenKind {
     LineComment,
     MultiStart(usize),
     MultiEnd(usize),
+    LineDocComment,
+    /// The opener of a doc comment, which is closed like the multi-line comment at that index
+    MultiDocStart(usize),
+    /// A string delimiter, which can both open and close strings
     String(usize),
+    /// A prefixed string delimiter, which can only open strings
+    StringStart(usize),
+    /// The delimiter of a prefixed string, which can only close strings
+    StringEnd(usize),
+    CharLiteral,
+    RawString(usize),
+    RawComment(usize),
+    Heredoc(usize),
+    InterpolationStart(usize),
+    InterpolationEnd(usize),
+    /// A bracket within an interpolation, which the next `InterpolationEnd` will close instead of the interpolation
+    InterpolationNest(usize),
+    Region(usize),
 }
 
-pub fn handle_input(config: LangConfig, input: impl BufRead, mut output: impl Write) {
-    let all_tokens = config
-        .multiline_comments
-        .iter()
-        .enumerate()
-        .flat_map(|(index, pair)| {
-            [
-                (pair.0.clone(), TokenKind::MultiStart(index)),
-                (pair.1.clone(), TokenKind::MultiEnd(index)),
-            ]
-        })
-        .chain(
-            config
-                .strings
-                .iter()
-                .enumerate()
-                .map(|(index, delimiter)| (delimiter.clone(), TokenKind::String(index))),
-        )
-        .chain(
-            config
-                .line_comments
-                .iter()
-                .map(|token| (token.clone(), TokenKind::LineComment)),
-        )
-        .collect::<Vec<_>>();
+/// The closer of the raw string or raw comment that is currently open
+#[derive(Clone, Debug)]
+struct RawEnd {
+    end: String,
+    comment: bool,
+}
 
-    let newline = "\n";
-    // A value that gets substituted in in-place of strings
-    let string_placeholder = format!(
-        "{0}…{0}",
-        config.strings.first().cloned().unwrap_or("\"".to_string())
-    );
+/// A heredoc whose opener was found, and which ends at its terminator
+#[derive(Clone, Debug)]
+struct Heredoc {
+    terminator: String,
+    indented: bool,
+    trailing_code: bool,
+}
 
-    let mut matches = Vec::with_capacity(64);
+impl Heredoc {
+    /// Returns the index at which the heredoc ends, if `line` is its terminator.
+    fn terminator_end(&self, line: &str) -> Option<usize> {
+        let indent = if self.indented {
+            line.len() - line.trim_start().len()
+        } else {
+            0
+        };
 
-    let mut multiline_comments: Vec<usize> = Vec::new();
-    let mut multiline_ranges = Ranges::empty();
+        let rest = line[indent..].strip_prefix(self.terminator.as_str())?;
+        let is_end = if self.trailing_code {
+            !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+        } else {
+            rest.trim_end().is_empty()
+        };
 
-    let mut current_string: Option<usize> = None;
-    let mut string_ranges = Ranges::empty();
+        is_end.then_some(line.len() - rest.len())
+    }
+}
 
-    for line in input.lines() {
-        multiline_ranges.next_line();
-        string_ranges.next_line();
-        let line = line.expect("Couldn't read from input!");
-        let mut line_range = Range::new(0, 0);
+/// A region of the input that is being parsed with the rules of another language, see [`RegionConfig`]
+struct Embedded<'a> {
+    end: String,
+    own_line: bool,
+    /// The parser of the embedded language, or `None` if it is unknown and the region is kept as-is
+    parser: Option<Box<Parser<'a>>>,
+}
 
-        for (token_string, token_kind) in all_tokens.iter() {
-            for (start, match_str) in line.match_indices(token_string) {
-                matches.push((Range::new(start, start + match_str.len()), *token_kind));
+/// A block of code being removed, see [`StripBlockConfig`]
+#[derive(Clone, Copy, Debug)]
+struct StripBlock {
+    index: usize,
+    /// The number of brackets that are open; the block hasn't opened yet if it is zero
+    depth: usize,
+    /// The indentation of the trigger's line, for indented blocks
+    indent: usize,
+    /// Whether the header of an indented block, up to its `:`, hasn't ended yet
+    in_header: bool,
+}
+
+impl StripBlock {
+    /// Follows the header of an indented block from `from` on, which ends at the first `:` outside of brackets;
+    /// `depth` then counts the brackets that are open.
+    fn scan_header(&mut self, line: &str, from: usize, in_code: impl Fn(usize) -> bool) {
+        for (index, c) in line[from..].char_indices() {
+            if !in_code(from + index) {
+                continue;
+            }
+            match c {
+                '(' | '[' | '{' => self.depth += 1,
+                ')' | ']' | '}' => self.depth = self.depth.saturating_sub(1),
+                ':' if self.depth == 0 => {
+                    self.in_header = false;
+                    return;
+                }
+                _ => {}
             }
         }
+    }
+}
+
+/// An annotation spanning several lines, see [`AnnotationConfig`]
+#[derive(Clone, Copy, Debug)]
+struct Annotation {
+    index: usize,
+    /// The number of brackets that are open
+    depth: usize,
+}
 
-        for blacklist in collect_blacklist_ranges(&line, &config.blacklist) {
-            matches.retain(|(range, _)| !range.overlaps(&blacklist));
+/// A region of code within a string, see [`StringConfig::interpolation`]
+#[derive(Clone, Copy, Debug)]
+struct Interpolation {
+    string: usize,
+    depth: usize,
+}
+
+fn string_tokens(index: usize, string: &StringConfig) -> Vec<(String, TokenKind)> {
+    let mut tokens = Vec::new();
+
+    if string.prefixes.is_empty() {
+        tokens.push((string.delimiter.clone(), TokenKind::String(index)));
+    } else {
+        for prefix in string.prefixes.iter() {
+            tokens.push((
+                format!("{}{}", prefix, string.delimiter),
+                TokenKind::StringStart(index),
+            ));
         }
+        tokens.push((string.delimiter.clone(), TokenKind::StringEnd(index)));
+    }
 
-        matches.sort_unstable_by_key(|(range, _)| range.start);
+    if let Some((start, end)) = &string.interpolation {
+        tokens.push((start.clone(), TokenKind::InterpolationStart(index)));
+        tokens.push((end.clone(), TokenKind::InterpolationEnd(index)));
+        if let Some(bracket) = start.chars().last() {
+            tokens.push((bracket.to_string(), TokenKind::InterpolationNest(index)));
+        }
+    }
 
-        for (range, token_kind) in matches.drain(..) {
-            match token_kind {
-                TokenKind::LineComment => {
-                    if multiline_ranges.contains(range.start) || string_ranges.contains(range.start)
-                    {
-                        continue;
-                    }
-                    line_range.widen(range.start, line.len() + 1);
-                }
-                TokenKind::MultiStart(index) => {
-                    if line_range.contains(range.start) || string_ranges.contains(range.start) {
-                        continue;
-                    }
+    tokens
+}
 
-                    if config.nested_comments {
-                        multiline_ranges.open(range.start);
-                        multiline_comments.push(index);
-                    } else if multiline_ranges.open_ranges.is_empty() {
-                        multiline_ranges.open(range.start);
-                    }
-                }
-                TokenKind::MultiEnd(index) => {
-                    if line_range.contains(range.start) || string_ranges.contains(range.start) {
-                        continue;
-                    }
+/// The number of lines at the beginning of a file within which generated markers are looked for.
+const GENERATED_MARKER_LINES: usize = 10;
+
+/// A `LangConfig` compiled into the list of tokens to look for, which can be reused across inputs.
+#[derive(Clone, Debug)]
+pub struct Stripper {
+    config: LangConfig,
+    tokens: Vec<(String, TokenKind)>,
+    /// A value that gets substituted in in-place of strings
+    string_placeholder: String,
+    /// The compiled languages of `config.embedded_langs`
+    embedded_langs: IndexMap<String, Stripper>,
+}
 
-                    // When nested comments are active, verify that the closing comment matches the opening comment;
-                    // otherwise, just close the multiline comment, if it is opened.
-                    if !config.nested_comments
-                        || multiline_comments
-                            .last()
-                            .map_or(false, |expected| *expected == index)
-                    {
-                        if config.nested_comments {
-                            multiline_comments.pop();
-                        }
+impl Stripper {
+    pub fn new(config: LangConfig) -> Self {
+        let tokens = config
+            .multiline_comments
+            .iter()
+            .enumerate()
+            .flat_map(|(index, pair)| {
+                [
+                    (pair.0.clone(), TokenKind::MultiStart(index)),
+                    (pair.1.clone(), TokenKind::MultiEnd(index)),
+                ]
+            })
+            .chain(
+                config
+                    .strings
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(index, string)| string_tokens(index, string)),
+            )
+            .chain(
+                config
+                    .char_literals
+                    .iter()
+                    .map(|delimiter| (delimiter.clone(), TokenKind::CharLiteral)),
+            )
+            .chain(
+                config
+                    .line_comments
+                    .iter()
+                    .map(|token| (token.clone(), TokenKind::LineComment)),
+            )
+            .chain(config.doc_comments.iter().map(|token| {
+                let multiline = config
+                    .multiline_comments
+                    .iter()
+                    .position(|(start, _)| token.starts_with(start.as_str()));
+                let kind = multiline.map_or(TokenKind::LineDocComment, TokenKind::MultiDocStart);
+                (token.clone(), kind)
+            }))
+            .collect::<Vec<_>>();
+
+        let string_placeholder = format!(
+            "{0}…{0}",
+            config
+                .strings
+                .first()
+                .map_or("\"", |string| string.delimiter.as_str())
+        );
 
-                        multiline_ranges.close(range.end);
-                    }
-                }
-                TokenKind::String(index) => {
-                    if line_range.contains(range.start) || multiline_ranges.contains(range.start) {
-                        debug_assert!(current_string.is_none());
-                        continue;
-                    }
+        let embedded_langs = config
+            .embedded_langs
+            .iter()
+            .map(|(name, lang_config)| (name.clone(), Stripper::new(lang_config.clone())))
+            .collect();
 
-                    match current_string {
-                        Some(expected) if expected == index => {
-                            current_string = None;
-                            string_ranges.close(range.end);
-                        }
-                        None => {
-                            current_string = Some(index);
-                            string_ranges.open(range.start);
+        Self {
+            config,
+            tokens,
+            string_placeholder,
+            embedded_langs,
+        }
+    }
+
+    pub fn config(&self) -> &LangConfig {
+        &self.config
+    }
+
+    /// Creates a new line-by-line parser, starting outside of any comment or string.
+    pub fn parser(&self) -> Parser<'_> {
+        Parser::new(self)
+    }
+
+    /// Splits `text` into consecutive segments of code, comments and strings.
+    /// The split is purely lexical: unlike [`Stripper::strip`], it disregards generated file markers,
+    /// strip blocks, hidden sections and annotations.
+    pub fn segments<'a>(&'a self, text: &'a str) -> Segments<'a> {
+        Segments {
+            parser: self.parser(),
+            lines: text.split_inclusive('\n'),
+            offset: 0,
+            queue: VecDeque::new(),
+            current: None,
+        }
+    }
+
+    /// Parses the whole input, returning the comments and strings found in each line.
+    pub fn parse(&self, input: impl BufRead) -> std::io::Result<Vec<LineRanges>> {
+        let mut parser = self.parser();
+        let (generated, lines) = self.read_lines(input)?;
+
+        lines
+            .map(|line| {
+                let line = line?;
+                Ok(if generated {
+                    LineRanges::removed(&line)
+                } else {
+                    parser.parse_line(&line)
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the lines of `input`, and whether it is a generated file, see [`LangConfig::generated_markers`].
+    fn read_lines(
+        &self,
+        input: impl BufRead,
+    ) -> std::io::Result<(bool, impl Iterator<Item = std::io::Result<String>>)> {
+        let mut lines = input.lines();
+        let header = lines
+            .by_ref()
+            .take(GENERATED_MARKER_LINES)
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        // Markers only count within comments, so that a string mentioning one doesn't give the file away;
+        // the text of prose languages is parsed as comments, but isn't one
+        let mut parser = self.parser();
+        let generated = !self.config.generated_markers.is_empty()
+            && !self.config.prose
+            && header.iter().any(|line| {
+                let line_ranges = parser.parse_line(line);
+                line_ranges.comments.iter().any(|range| {
+                    let comment = &line[range.start..range.end.min(line.len())];
+                    self.config
+                        .generated_markers
+                        .iter()
+                        .any(|marker| marker.is_match(comment))
+                })
+            });
+
+        Ok((generated, header.into_iter().map(Ok).chain(lines)))
+    }
+
+    /// Writes `input` to `output`, with comments (and strings, unless `keep_strings` is set) stripped away.
+    /// Every line of the input results in exactly one line in the output, and generated files are removed entirely.
+    /// If `mask` is set, the removed text is replaced by spaces instead, so that every line keeps its length and layout.
+    pub fn strip(&self, input: impl BufRead, mut output: impl Write) -> std::io::Result<()> {
+        let newline = "\n";
+        let keep_strings = self.config.keep_strings;
+        let mut parser = self.parser();
+        let (generated, lines) = self.read_lines(input)?;
+
+        for line in lines {
+            let line = line?;
+            let line_ranges = if generated {
+                LineRanges::removed(&line)
+            } else {
+                parser.parse_line(&line)
+            };
+
+            if self.config.mask || self.config.only.is_some() {
+                let mut kept = String::with_capacity(line.len());
+                for (index, c) in line.char_indices() {
+                    if !line_ranges.is_stripped(index, &self.config) {
+                        kept.push(c);
+                    } else if self.config.mask {
+                        // Multi-byte characters are replaced by as many spaces, to keep byte offsets intact
+                        if c == '\t' {
+                            kept.push(c);
+                        } else {
+                            kept.extend(std::iter::repeat_n(' ', c.len_utf8()));
                         }
-                        _ => {}
                     }
                 }
+                output.write_all(kept.as_bytes())?;
+                output.write_all(newline.as_bytes())?;
+                continue;
             }
-        }
 
-        if !config.nested_comments {
-            debug_assert!(multiline_comments.len() == 0);
-            debug_assert!(multiline_ranges.open_ranges.len() <= 1);
-        }
-
-        let mut negative_range = NegativeRange::new(line.len());
-        line_range.remove(&mut negative_range);
-        multiline_ranges.remove(&mut negative_range);
-        if !config.keep_strings {
-            string_ranges.remove(&mut negative_range);
-        }
+            let kept_comments = if self.config.keep_doc_comments {
+                line_ranges
+                    .kept
+                    .iter()
+                    .chain(line_ranges.docs.iter())
+                    .collect::<Vec<_>>()
+            } else {
+                line_ranges.kept.iter().collect()
+            };
+
+            let mut negative_range = NegativeRange::new(line.len());
+            for range in line_ranges.comments.iter() {
+                // Only remove the parts of the comment that lie outside of the kept comments
+                let mut removed = NegativeRange {
+                    ranges: vec![*range],
+                };
+                for kept in kept_comments.iter() {
+                    kept.remove(&mut removed);
+                }
+                for part in removed.ranges {
+                    part.remove(&mut negative_range);
+                }
+            }
+            if !keep_strings {
+                for range in line_ranges.strings.iter() {
+                    range.remove(&mut negative_range);
+                }
+            }
+            for range in line_ranges.removed_code(&self.config) {
+                range.remove(&mut negative_range);
+            }
 
-        if line.is_empty() {
-            output
-                .write(newline.as_bytes())
-                .expect("Couldn't write to output!");
-        } else {
             let mut slices = negative_range
                 .ranges
                 .into_iter()
-                .map(|range| {
-                    (
-                        IoSlice::new(
-                            line[range.start..=range.end.min(line.len().saturating_sub(1))]
-                                .as_bytes(),
-                        ),
-                        range.start,
-                    )
-                })
+                .map(|range| (&line.as_bytes()[range.start..range.end], range.start))
                 .collect::<Vec<_>>();
 
-            if !config.keep_strings {
-                slices = slices
-                    .into_iter()
-                    .chain(
-                        string_ranges
-                            .open_ranges
-                            .iter()
-                            .map(|start| Range::new(*start, line.len() + 1))
-                            .chain(string_ranges.closed_ranges.iter().cloned())
-                            .map(|range| {
-                                (IoSlice::new(string_placeholder.as_bytes()), range.start)
-                            }),
-                    )
-                    .collect();
-            }
-
-            slices.sort_unstable_by_key(|pair| pair.1);
-            let slices = slices
-                .into_iter()
-                .map(|pair| pair.0)
-                .chain([IoSlice::new(newline.as_bytes())])
-                .collect::<Vec<_>>();
+            if !keep_strings {
+                slices.extend(
+                    line_ranges
+                        .strings
+                        .iter()
+                        .filter(|range| {
+                            !line_ranges
+                                .removed_code(&self.config)
+                                .any(|removed| removed.overlaps(range))
+                        })
+                        .map(|range| (self.string_placeholder.as_bytes(), range.start)),
+                );
+            }
 
-            output
-                .write_vectored(&slices)
-                .expect("Couldn't write to output!");
+            slices.sort_by_key(|pair| pair.1);
+            for (slice, _) in slices {
+                output.write_all(slice)?;
+            }
+            output.write_all(newline.as_bytes())?;
         }
+
+        Ok(())
     }
 }
 
-fn collect_blacklist_ranges(line: &str, blacklists: &[String]) -> Vec<Range> {
-    let mut ranges = blacklists
-        .iter()
-        .flat_map(|blacklist| line.match_indices(blacklist))
-        .map(|(start, string)| Range::new(start, start + string.len()))
-        .collect::<Vec<_>>();
-
-    ranges.sort_unstable_by_key(|range| range.start);
-
-    ranges
-        .iter()
-        .copied()
-        .enumerate()
-        .filter(|&(i, range)| !ranges.iter().take(i).any(|other| other.overlaps(&range)))
-        .map(|pair| pair.1)
-        .collect()
+/// The comments and strings found within a single line.
+/// Ranges that continue onto the next line extend past the end of the line.
+#[derive(Clone, Debug, Default)]
+pub struct LineRanges {
+    comments: Vec<Range>,
+    strings: Vec<Range>,
+    /// The doc comments, which are also part of `comments`
+    docs: Vec<Range>,
+    /// The comments matching [`LangConfig::keep_comments_matching`], which are also part of `comments`
+    kept: Vec<Range>,
+    /// The parts of [`LangConfig::strip_blocks`], which are removed whatever they contain
+    blocks: Vec<Range>,
+    /// The annotations, which are removed whatever they contain if [`LangConfig::remove_annotations`] is set
+    annotations: Vec<Range>,
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    fn test_handle_input(config: LangConfig, input: &'static str, expected: &'static str) {
-        let mut output: Vec<u8> = Vec::new();
-        handle_input(config, std::io::Cursor::new(input), &mut output);
-
-        let output = String::from_utf8(output).expect("handle_input did not return valid UTF-8");
-        assert_eq!(output, expected);
+impl LineRanges {
+    /// The ranges of a line which is removed as a whole, like those of generated files.
+    fn removed(line: &str) -> Self {
+        Self {
+            blocks: vec![Range::new(0, line.len())],
+            ..Default::default()
+        }
     }
 
-    #[test]
-    fn test_overlap() {
-        fn test_overlap([xmin, xsup]: [usize; 2], [ymin, ysup]: [usize; 2], positive: bool) {
-            assert_eq!(
-                Range::new(xmin, xsup).overlaps(&Range::new(ymin, ysup)),
-                positive,
-                "Range [{xmin}; {xsup}) should{} overlap with [{ymin}; {ysup})",
-                if positive { "" } else { "n't" }
-            );
+    /// Returns true if the byte at `index` is part of a comment,
+    /// or part of a string if `keep_strings` is false.
+    pub fn is_removed(&self, index: usize, keep_strings: bool) -> bool {
+        self.comments.iter().any(|range| range.contains(index))
+            || !keep_strings && self.strings.iter().any(|range| range.contains(index))
+    }
 
-            assert_eq!(
-                Range::new(ymin, ysup).overlaps(&Range::new(xmin, xsup)),
-                positive,
-                "Range [{ymin}; {ysup}) should{} overlap with [{xmin}; {xsup})",
-                if positive { "" } else { "n't" }
-            );
+    /// Returns true if the byte at `index` is left out of the output of a [`Stripper`] using `config`,
+    /// taking [`LangConfig::only`] into account.
+    pub fn is_stripped(&self, index: usize, config: &LangConfig) -> bool {
+        let is_doc = || self.docs.iter().any(|range| range.contains(index));
+        if self.removed_code(config).any(|range| range.contains(index)) {
+            return true;
         }
 
-        test_overlap([0, 0], [0, 100], false);
-        test_overlap([50, 50], [0, 100], false);
-        test_overlap([100, 100], [0, 100], false);
-        test_overlap([0, 1], [0, 1], true);
-        test_overlap([0, 1], [1, 2], false);
-        test_overlap([1, 2], [0, 1], false);
-        test_overlap([5, 10], [9, 12], true);
-        test_overlap([5, 10], [3, 6], true);
-        test_overlap([5, 10], [10, 12], false);
-        test_overlap([5, 10], [3, 5], false);
-        test_overlap([5, 10], [4, 11], true);
-        test_overlap([5, 10], [3, 12], true);
+        match config.only {
+            None => {
+                self.is_removed(index, config.keep_strings)
+                    && !(config.keep_doc_comments && is_doc())
+                    && !self.kept.iter().any(|range| range.contains(index))
+            }
+            Some(Only::Comments) => !self.comments.iter().any(|range| range.contains(index)),
+            Some(Only::Strings) => !self.strings.iter().any(|range| range.contains(index)),
+            Some(Only::Docs) => !is_doc(),
+        }
     }
 
-    #[test]
-    fn test_noop() {
-        test_handle_input(LangConfig::default(), "\n", "\n");
-
-        test_handle_input(LangConfig::default(), "abc", "abc\n");
-        test_handle_input(LangConfig::default().nested_comments(true), "abc", "abc\n");
-
-        test_handle_input(LangConfig::default(), "abc\n", "abc\n");
-        test_handle_input(
-            LangConfig::default().nested_comments(true),
-            "abc\n",
-            "abc\n",
-        );
+    /// The ranges that are removed along with everything they contain: strip blocks, and annotations if they are removed.
+    fn removed_code<'a>(&'a self, config: &LangConfig) -> impl Iterator<Item = &'a Range> {
+        let annotations = if config.remove_annotations {
+            self.annotations.as_slice()
+        } else {
+            &[]
+        };
 
-        test_handle_input(LangConfig::default(), "abc\ndef", "abc\ndef\n");
-        test_handle_input(
-            LangConfig::default().nested_comments(true),
-            "abc\ndef",
-            "abc\ndef\n",
-        );
+        self.blocks.iter().chain(annotations)
     }
 
-    #[test]
-    fn test_singleline() {
-        let config = LangConfig::default().line_comment("//");
+    /// Adds the ranges of `other`, which were found within the slice of the line beginning at `offset`,
+    /// clamping them to `end`.
+    fn extend(&mut self, other: LineRanges, offset: usize, end: usize) {
+        let shift = |range: Range| Range::new(range.start + offset, (range.end + offset).min(end));
 
-        test_handle_input(config.clone(), "hello // world", "hello \n");
-        test_handle_input(
-            config.clone(),
-            "hello // world\nuntouched",
-            "hello \nuntouched\n",
+        self.comments.extend(
+            other
+                .comments
+                .into_iter()
+                .map(shift)
+                .filter(|range| range.start < range.end),
         );
-        test_handle_input(config.clone(), "hello // world // hey", "hello \n");
-        test_handle_input(config.clone(), "hello // world\n// hey", "hello \n\n");
-    }
-
-    #[test]
-    fn test_multiline() {
-        let config = LangConfig::default().multiline_comment("/*", "*/");
-
-        test_handle_input(config.clone(), "hello /* world", "hello \n");
-        test_handle_input(config.clone(), "hello */ world", "hello */ world\n");
-        test_handle_input(config.clone(), "hello /* world */", "hello \n");
-        test_handle_input(
-            config.clone(),
-            "hello /* world */\na very long line with a lot of text",
-            "hello \na very long line with a lot of text\n",
+        self.strings.extend(
+            other
+                .strings
+                .into_iter()
+                .map(shift)
+                .filter(|range| range.start < range.end),
         );
-        test_handle_input(
-            config.clone(),
-            "hello /* world\n*/newline",
-            "hello \nnewline\n",
+        self.docs.extend(
+            other
+                .docs
+                .into_iter()
+                .map(shift)
+                .filter(|range| range.start < range.end),
         );
-        test_handle_input(
-            config.clone(),
-            "hello /* world */ included",
-            "hello  included\n",
+        self.kept.extend(
+            other
+                .kept
+                .into_iter()
+                .map(shift)
+                .filter(|range| range.start < range.end),
+        );
+        self.blocks.extend(
+            other
+                .blocks
+                .into_iter()
+                .map(shift)
+                .filter(|range| range.start < range.end),
+        );
+        self.annotations.extend(
+            other
+                .annotations
+                .into_iter()
+                .map(shift)
+                .filter(|range| range.start < range.end),
         );
     }
+}
 
-    #[test]
-    fn test_interactions() {
-        let config = LangConfig::default()
-            .multiline_comment("/*", "*/")
-            .line_comment("//");
+/// Line-by-line parser, which keeps track of the comments and strings spanning multiple lines.
+pub struct Parser<'a> {
+    config: &'a LangConfig,
+    tokens: &'a [(String, TokenKind)],
+    embedded_langs: &'a IndexMap<String, Stripper>,
+    matches: Vec<(Range, TokenKind)>,
+
+    multiline_comments: Vec<usize>,
+    multiline_ranges: Ranges,
+    /// The multi-line comments that are doc comments, which are also part of `multiline_ranges`
+    doc_ranges: Ranges,
+    /// The multi-line comments matching `keep_comments_matching`, which are also part of `multiline_ranges`
+    kept_ranges: Ranges,
+    /// The strings that are docstrings, which are also part of `string_ranges` while parsing
+    docstring_ranges: Ranges,
+
+    current_string: Option<usize>,
+    interpolations: Vec<Interpolation>,
+    raw_end: Option<RawEnd>,
+    /// Heredocs whose opener was found; the first one is open if its opener was on a previous line
+    heredocs: VecDeque<Heredoc>,
+    string_ranges: Ranges,
+
+    embedded: Option<Embedded<'a>>,
+
+    /// The number of preprocessor conditionals that are open
+    conditional_depth: usize,
+    /// The depth of the conditional whose branch is disabled, if any
+    disabled_conditional: Option<usize>,
+
+    strip_block: Option<StripBlock>,
+    /// The hidden sections that are open, as indices into `config.hidden_sections`
+    hidden_sections: Vec<usize>,
+    annotation: Option<Annotation>,
+}
 
-        // A multiline comment in a single-line comment should be ignored
-        test_handle_input(
-            config.clone(),
-            "hello // /* world\nincluded",
-            "hello \nincluded\n",
-        );
-        test_handle_input(
-            config.clone(),
-            "hello // /* world\nincluded */",
-            "hello \nincluded */\n",
-        );
+impl<'a> Parser<'a> {
+    fn new(stripper: &'a Stripper) -> Self {
+        Self {
+            config: &stripper.config,
+            tokens: &stripper.tokens,
+            embedded_langs: &stripper.embedded_langs,
+            matches: Vec::with_capacity(64),
+
+            multiline_comments: Vec::new(),
+            multiline_ranges: Ranges::empty(),
+            doc_ranges: Ranges::empty(),
+            kept_ranges: Ranges::empty(),
+            docstring_ranges: Ranges::empty(),
+
+            current_string: None,
+            interpolations: Vec::new(),
+            raw_end: None,
+            heredocs: VecDeque::new(),
+            string_ranges: Ranges::empty(),
+
+            embedded: None,
+
+            conditional_depth: 0,
+            disabled_conditional: None,
+
+            strip_block: None,
+            hidden_sections: Vec::new(),
+            annotation: None,
+        }
+    }
 
-        // A single-line comment in a multiline comment should be ignored
-        test_handle_input(config.clone(), "/* // */hello world", "hello world\n");
+    /// Parses the next line of input, which should not contain its line terminator.
+    pub fn parse_line(&mut self, line: &str) -> LineRanges {
+        let continued = !self.in_code();
+        let disabled = self.disabled_line(line);
+        self.multiline_ranges.next_line();
+        self.doc_ranges.next_line();
+        self.kept_ranges.next_line();
+        self.docstring_ranges.next_line();
+        self.string_ranges.next_line();
+        let mut line_range = Range::new(0, 0);
+        let mut line_doc = false;
+        let mut line_kept = false;
+        let mut embedded_ranges = LineRanges::default();
+
+        // The index at which the current language's rules begin to apply
+        let mut start = 0;
+        loop {
+            if let Some(embedded) = &mut self.embedded {
+                let rest = &line[start..];
+                let end = if embedded.end.is_empty() {
+                    Some(rest.len())
+                } else if embedded.own_line {
+                    let is_closer = line
+                        .trim()
+                        .strip_prefix(embedded.end.as_str())
+                        .is_some_and(|rest| rest.chars().all(|c| embedded.end.ends_with(c)));
+                    (start == 0 && is_closer).then_some(0)
+                } else {
+                    rest.find(embedded.end.as_str())
+                };
+
+                let region_end = end.map_or(line.len() + 1, |end| start + end);
+                if let Some(parser) = &mut embedded.parser {
+                    let ranges = parser.parse_line(&rest[..end.unwrap_or(rest.len())]);
+                    embedded_ranges.extend(ranges, start, region_end);
+                }
+
+                let Some(end) = end else {
+                    break;
+                };
+
+                // The closer belongs to the host language, but isn't parsed again with its rules;
+                // one standing on its own line takes its indentation along
+                let closer_len = if embedded.own_line {
+                    line.trim_end().len()
+                } else {
+                    embedded.end.len()
+                };
+                let closer = Range::new(start + end, start + end + closer_len);
+                if self.config.prose {
+                    self.multiline_ranges.push(closer);
+                }
+                self.embedded = None;
+                start = closer.end;
+            }
+
+            match self.parse_code(line, start, &mut line_range, &mut line_doc, &mut line_kept) {
+                Some(region_start) => start = region_start,
+                None => break,
+            }
+        }
+
+        if !self.config.nested_comments {
+            debug_assert!(self.multiline_comments.is_empty());
+            debug_assert!(self.multiline_ranges.open_ranges.len() <= 1);
+        }
+
+        // Docstrings are parsed as strings, but end up as doc comments
+        let docstrings = self.docstring_ranges.to_vec(line.len());
+
+        let mut comments = self.multiline_ranges.to_vec(line.len());
+        if line_range.start != line_range.end {
+            comments.push(line_range);
+        }
+        comments.extend(docstrings.iter().copied());
+        comments.extend(embedded_ranges.comments);
+
+        let mut strings = self.string_ranges.to_vec(line.len());
+        strings.retain(|range| !docstrings.contains(range));
+        strings.extend(embedded_ranges.strings);
+
+        let mut docs = self.doc_ranges.to_vec(line.len());
+        if line_doc {
+            docs.push(line_range);
+        }
+        docs.extend(docstrings);
+        docs.extend(embedded_ranges.docs);
+
+        let mut kept = self.kept_ranges.to_vec(line.len());
+        if line_kept {
+            kept.push(line_range);
+        }
+        kept.extend(embedded_ranges.kept);
+
+        if disabled {
+            // Like the preprocessor, only track comments within disabled branches,
+            // so that a stray quote, as in `don't`, doesn't open a string that never closes
+            if self.current_string.take().is_some() {
+                self.string_ranges.close(line.len());
+                self.interpolations.clear();
+            }
+
+            // The whole line is a comment, which may still continue onto the next line
+            let end = comments
+                .iter()
+                .map(|range| range.end)
+                .fold(line.len(), usize::max);
+            let comments = vec![Range::new(0, end)];
+            return LineRanges {
+                blocks: self.strip_blocks(line, continued, &comments, &[]),
+                comments,
+                ..Default::default()
+            };
+        }
+
+        let mut blocks = self.strip_blocks(line, continued, &comments, &strings);
+        blocks.extend(self.hidden_sections(line, &comments));
+
+        LineRanges {
+            blocks,
+            annotations: self.annotations(line, &comments, &strings),
+            comments,
+            strings,
+            docs,
+            kept,
+        }
+    }
+
+    /// Returns true if the next line begins in code, rather than within a comment, a string or a region.
+    fn in_code(&self) -> bool {
+        self.multiline_ranges.open_ranges.is_empty()
+            && self.current_string.is_none()
+            && self.raw_end.is_none()
+            && self.heredocs.is_empty()
+            && self.embedded.is_none()
+    }
+
+    /// Updates the preprocessor conditionals with the directive that `line` may hold,
+    /// returning true if the line lies within a disabled branch.
+    fn disabled_line(&mut self, line: &str) -> bool {
+        let Some(conditionals) = &self.config.conditionals else {
+            return false;
+        };
+
+        // Directives are only recognized in code
+        let disabled = self.disabled_conditional.is_some();
+        if !self.in_code() {
+            return disabled;
+        }
+
+        if conditionals.alternative.is_match(line) {
+            let Some(depth) = self.conditional_depth.checked_sub(1) else {
+                return disabled;
+            };
+            if disabled && self.disabled_conditional != Some(depth) {
+                // The conditional is nested within a disabled branch
+                return true;
+            }
+
+            self.disabled_conditional = conditionals.disabled.is_match(line).then_some(depth);
+            false
+        } else if conditionals.disabled.is_match(line) || conditionals.start.is_match(line) {
+            if !disabled && conditionals.disabled.is_match(line) {
+                self.disabled_conditional = Some(self.conditional_depth);
+            }
+            self.conditional_depth += 1;
+            disabled
+        } else if conditionals.end.is_match(line) {
+            self.conditional_depth = self.conditional_depth.saturating_sub(1);
+            if self.disabled_conditional == Some(self.conditional_depth) {
+                self.disabled_conditional = None;
+                return false;
+            }
+            disabled
+        } else {
+            disabled
+        }
+    }
+
+    /// Finds the parts of `line` that belong to strip blocks, given its `comments` and `strings`, outside of which brackets are counted.
+    /// `continued` is whether the line begins within a comment, string or region.
+    fn strip_blocks(
+        &mut self,
+        line: &str,
+        continued: bool,
+        comments: &[Range],
+        strings: &[Range],
+    ) -> Vec<Range> {
+        let in_code = |index: usize| {
+            !comments.iter().any(|range| range.contains(index))
+                && !strings.iter().any(|range| range.contains(index))
+        };
+        let indent = line.len() - line.trim_start().len();
+
+        if let Some(block) = self
+            .strip_block
+            .as_mut()
+            .filter(|block| self.config.strip_blocks[block.index].indented)
+        {
+            // The header may span several lines, like a signature whose arguments are on lines of their own
+            if block.in_header {
+                block.scan_header(line, 0, in_code);
+                return vec![Range::new(0, line.len())];
+            }
+            if continued
+                || indent == line.len()
+                || indent > block.indent
+                || comments.iter().any(|range| range.contains(indent))
+            {
+                return vec![Range::new(0, line.len())];
+            }
+            self.strip_block = None;
+        }
+
+        let mut blocks = Vec::new();
+        let mut block_start = 0;
+        let mut index = 0;
+        loop {
+            let Some(block) = &mut self.strip_block else {
+                // Finds the first trigger that lies within code
+                let found = self
+                    .config
+                    .strip_blocks
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(block_index, strip_block)| {
+                        strip_block
+                            .trigger
+                            .find_iter(line)
+                            .find(|found| found.start() >= index && in_code(found.start()))
+                            .map(|found| (block_index, found))
+                    })
+                    .min_by_key(|(_, found)| found.start());
+                let Some((block_index, found)) = found else {
+                    break;
+                };
+
+                let mut block = StripBlock {
+                    index: block_index,
+                    depth: 0,
+                    indent,
+                    in_header: false,
+                };
+                if self.config.strip_blocks[block_index].indented {
+                    block.in_header = true;
+                    block.scan_header(line, found.end(), in_code);
+                    self.strip_block = Some(block);
+                    blocks.push(Range::new(found.start(), line.len()));
+                    break;
+                }
+                self.strip_block = Some(block);
+                block_start = found.start();
+                index = found.end();
+                continue;
+            };
+
+            let strip_block = &self.config.strip_blocks[block.index];
+            let mut end = None;
+            while index < line.len() {
+                if !line.is_char_boundary(index) || !in_code(index) {
+                    index += 1;
+                    continue;
+                }
+
+                let rest = &line[index..];
+                if rest.starts_with(strip_block.open.as_str()) {
+                    block.depth += 1;
+                    index += strip_block.open.len();
+                } else if block.depth > 0 && rest.starts_with(strip_block.close.as_str()) {
+                    block.depth -= 1;
+                    index += strip_block.close.len();
+                    if block.depth == 0 {
+                        end = Some(index);
+                        break;
+                    }
+                } else if block.depth == 0
+                    && strip_block
+                        .terminators
+                        .iter()
+                        .any(|terminator| rest.starts_with(terminator.as_str()))
+                {
+                    // No block follows the trigger, although the lines before this one were already removed
+                    break;
+                } else {
+                    index += 1;
+                }
+            }
+
+            match end {
+                Some(end) => {
+                    blocks.push(Range::new(block_start, end));
+                    self.strip_block = None;
+                }
+                None if index < line.len() => self.strip_block = None,
+                None => {
+                    blocks.push(Range::new(block_start, line.len()));
+                    break;
+                }
+            }
+        }
+
+        blocks
+    }
+
+    /// Finds the annotations within `line`, given its `comments` and `strings`, outside of which brackets are counted.
+    fn annotations(&mut self, line: &str, comments: &[Range], strings: &[Range]) -> Vec<Range> {
+        let in_code = |index: usize| {
+            !comments.iter().any(|range| range.contains(index))
+                && !strings.iter().any(|range| range.contains(index))
+        };
+
+        let mut annotations = Vec::new();
+        let mut annotation_start = 0;
+        let mut index = 0;
+        loop {
+            let Some(annotation) = &mut self.annotation else {
+                // Finds the first opener that lies within code
+                let found = self
+                    .config
+                    .annotations
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(annotation_index, annotation)| {
+                        annotation
+                            .start
+                            .find_iter(line)
+                            .find(|found| found.start() >= index && in_code(found.start()))
+                            .map(|found| (annotation_index, found))
+                    })
+                    .min_by_key(|(_, found)| found.start());
+                let Some((annotation_index, found)) = found else {
+                    break;
+                };
+
+                // The opener may itself open brackets, like Rust's `#[`
+                let (open, close) = &self.config.annotations[annotation_index].brackets;
+                let depth = found
+                    .as_str()
+                    .matches(open.as_str())
+                    .count()
+                    .saturating_sub(found.as_str().matches(close.as_str()).count());
+                if depth == 0 && !line[found.end()..].starts_with(open.as_str()) {
+                    annotations.push(Range::new(found.start(), found.end()));
+                    index = found.end();
+                    continue;
+                }
+
+                self.annotation = Some(Annotation {
+                    index: annotation_index,
+                    depth,
+                });
+                annotation_start = found.start();
+                index = found.end();
+                continue;
+            };
+
+            let (open, close) = &self.config.annotations[annotation.index].brackets;
+            let mut end = None;
+            while index < line.len() {
+                if !line.is_char_boundary(index) || !in_code(index) {
+                    index += 1;
+                    continue;
+                }
+
+                let rest = &line[index..];
+                if rest.starts_with(open.as_str()) {
+                    annotation.depth += 1;
+                    index += open.len();
+                } else if rest.starts_with(close.as_str()) {
+                    annotation.depth = annotation.depth.saturating_sub(1);
+                    index += close.len();
+                    if annotation.depth == 0 {
+                        end = Some(index);
+                        break;
+                    }
+                } else {
+                    index += 1;
+                }
+            }
+
+            match end {
+                Some(end) => {
+                    annotations.push(Range::new(annotation_start, end));
+                    self.annotation = None;
+                }
+                None => {
+                    annotations.push(Range::new(annotation_start, line.len()));
+                    break;
+                }
+            }
+        }
+
+        annotations
+    }
+
+    /// Finds the parts of `line` that lie within hidden sections, given its `comments`, which may be their markers.
+    fn hidden_sections(&mut self, line: &str, comments: &[Range]) -> Vec<Range> {
+        let sections = &self.config.hidden_sections;
+        if sections.is_empty() {
+            return Vec::new();
+        }
+
+        let mut comments = comments.to_vec();
+        comments.sort_by_key(|range| range.start);
+
+        let mut hidden = Vec::new();
+        let mut section_start = 0;
+        let mut position = 0;
+        for comment in comments {
+            // Nested comments are part of the comment they are in
+            if comment.start < position {
+                continue;
+            }
+            let end = comment.end.min(line.len());
+            position = comment.end;
+            let text = &line[comment.start..end];
+
+            let closes = self
+                .hidden_sections
+                .last()
+                .is_some_and(|index| sections[*index].end.is_match(text));
+            if closes {
+                self.hidden_sections.pop();
+                if self.hidden_sections.is_empty() {
+                    hidden.push(Range::new(section_start, end));
+                }
+            } else if let Some(index) = sections
+                .iter()
+                .position(|section| section.start.is_match(text))
+            {
+                if self.hidden_sections.is_empty() {
+                    section_start = comment.start;
+                }
+                self.hidden_sections.push(index);
+            }
+        }
+
+        if !self.hidden_sections.is_empty() {
+            hidden.push(Range::new(section_start, line.len()));
+        }
+
+        hidden
+    }
+
+    /// Parses `line` from `start` onwards with the rules of the current language,
+    /// until the end of the line or until a region opens, in which case the index at which it begins is returned.
+    /// `line_range` is the line comment, if one was found, `line_doc` whether it is a doc comment,
+    /// and `line_kept` whether it matches `keep_comments_matching`.
+    fn parse_code(
+        &mut self,
+        line: &str,
+        start: usize,
+        line_range: &mut Range,
+        line_doc: &mut bool,
+        line_kept: &mut bool,
+    ) -> Option<usize> {
+        let config = self.config;
+        let multiline_ranges = &mut self.multiline_ranges;
+        let string_ranges = &mut self.string_ranges;
+
+        for (token_string, token_kind) in self.tokens {
+            for (start, match_str) in line.match_indices(token_string) {
+                self.matches
+                    .push((Range::new(start, start + match_str.len()), *token_kind));
+            }
+        }
+
+        for (index, heredoc) in config.heredocs.iter().enumerate() {
+            for found in heredoc.start.find_iter(line) {
+                self.matches.push((
+                    Range::new(found.start(), found.end()),
+                    TokenKind::Heredoc(index),
+                ));
+            }
+        }
+
+        for (index, region) in config.regions.iter().enumerate() {
+            for found in region.start.find_iter(line) {
+                if !found.is_empty() {
+                    self.matches.push((
+                        Range::new(found.start(), found.end()),
+                        TokenKind::Region(index),
+                    ));
+                }
+            }
+        }
+
+        for blacklist in collect_blacklist_ranges(line, &config.blacklist) {
+            self.matches
+                .retain(|(range, _)| !range.overlaps(&blacklist));
+        }
+
+        for (index, raw_string) in config.raw_strings.iter().enumerate() {
+            for found in raw_string.start.find_iter(line) {
+                self.matches.push((
+                    Range::new(found.start(), found.end()),
+                    TokenKind::RawString(index),
+                ));
+            }
+        }
+
+        for (index, raw_comment) in config.raw_comments.iter().enumerate() {
+            for found in raw_comment.start.find_iter(line) {
+                self.matches.push((
+                    Range::new(found.start(), found.end()),
+                    TokenKind::RawComment(index),
+                ));
+            }
+        }
+
+        // When several tokens begin at the same place, the longest one takes precedence
+        self.matches
+            .sort_unstable_by_key(|(range, _)| (range.start, std::cmp::Reverse(range.end)));
+
+        // Tokens that begin before this index overlap with a token that was already handled
+        let mut skip_until = start;
+        // The characters before this index were already checked for escapes
+        let mut escape_from = 0;
+
+        if let Some(heredoc) = self.heredocs.front() {
+            match heredoc.terminator_end(line) {
+                Some(end) => {
+                    string_ranges.push(Range::new(0, end));
+                    skip_until = end;
+                    self.heredocs.pop_front();
+                }
+                None => {
+                    string_ranges.push(Range::new(0, line.len() + 1));
+                    skip_until = line.len();
+                }
+            }
+        } else if let Some(raw_end) = &self.raw_end {
+            match line.find(raw_end.end.as_str()) {
+                Some(index) => {
+                    skip_until = index + raw_end.end.len();
+                    if raw_end.comment {
+                        multiline_ranges.close(skip_until);
+                        self.kept_ranges.close(skip_until);
+                    } else {
+                        string_ranges.close(skip_until);
+                    }
+                    self.raw_end = None;
+                }
+                None => skip_until = line.len(),
+            }
+        }
+
+        for (range, token_kind) in self.matches.drain(..) {
+            if range.start < skip_until {
+                continue;
+            }
+            escape_from = escape_from.max(skip_until);
+            if config.prose && !matches!(token_kind, TokenKind::Region(_)) {
+                continue;
+            }
+
+            match token_kind {
+                TokenKind::LineComment => {
+                    if line_range.contains(range.start)
+                        || multiline_ranges.contains(range.start)
+                        || string_ranges.contains(range.start)
+                    {
+                        continue;
+                    }
+                    line_range.widen(range.start, line.len());
+                    *line_kept = keeps_comment(config, &line[range.end..]);
+                }
+                TokenKind::MultiStart(index) => {
+                    if line_range.contains(range.start) || string_ranges.contains(range.start) {
+                        continue;
+                    }
+
+                    // Comments nested within a kept comment are kept along with it
+                    if multiline_ranges.open_ranges.is_empty()
+                        && keeps_comment(config, &line[range.end..])
+                    {
+                        self.kept_ranges.open(range.start);
+                    }
+
+                    if config.nested_comments {
+                        multiline_ranges.open(range.start);
+                        self.multiline_comments.push(index);
+                    } else if multiline_ranges.open_ranges.is_empty() {
+                        multiline_ranges.open(range.start);
+                    } else {
+                        continue;
+                    }
+                    skip_until = range.end;
+                }
+                TokenKind::LineDocComment => {
+                    if line_range.contains(range.start)
+                        || multiline_ranges.contains(range.start)
+                        || string_ranges.contains(range.start)
+                    {
+                        continue;
+                    }
+                    line_range.widen(range.start, line.len());
+                    *line_doc = true;
+                    *line_kept = keeps_comment(config, &line[range.end..]);
+                }
+                TokenKind::MultiDocStart(index) => {
+                    // Doc comments within other comments are part of those
+                    if line_range.contains(range.start)
+                        || string_ranges.contains(range.start)
+                        || !multiline_ranges.open_ranges.is_empty()
+                    {
+                        continue;
+                    }
+
+                    // `/**/` is an empty comment rather than the start of a doc comment
+                    let (start, end) = &config.multiline_comments[index];
+                    if line[range.start + start.len()..].starts_with(end.as_str()) {
+                        continue;
+                    }
+
+                    multiline_ranges.open(range.start);
+                    if config.nested_comments {
+                        self.multiline_comments.push(index);
+                    }
+                    self.doc_ranges.open(range.start);
+                    if keeps_comment(config, &line[range.end..]) {
+                        self.kept_ranges.open(range.start);
+                    }
+                    skip_until = range.end;
+                }
+                TokenKind::MultiEnd(index) => {
+                    if line_range.contains(range.start) || string_ranges.contains(range.start) {
+                        continue;
+                    }
+
+                    // When nested comments are active, verify that the closing comment matches the opening comment;
+                    // otherwise, just close the multiline comment, if it is opened.
+                    if !config.nested_comments
+                        || self
+                            .multiline_comments
+                            .last()
+                            .is_some_and(|expected| *expected == index)
+                    {
+                        if config.nested_comments {
+                            self.multiline_comments.pop();
+                        }
+
+                        if !multiline_ranges.open_ranges.is_empty() {
+                            multiline_ranges.close(range.end);
+                            skip_until = range.end;
+
+                            if multiline_ranges.open_ranges.is_empty() {
+                                self.doc_ranges.close(range.end);
+                                self.kept_ranges.close(range.end);
+                            }
+                        }
+                    }
+                }
+                TokenKind::String(index)
+                | TokenKind::StringStart(index)
+                | TokenKind::StringEnd(index) => {
+                    if line_range.contains(range.start) || multiline_ranges.contains(range.start) {
+                        debug_assert!(self.current_string.is_none());
+                        continue;
+                    }
+
+                    let escape = escape_of(config, self.current_string);
+                    escape_from = skip_escapes(line, escape_from, range.start, escape);
+                    if escape_from != range.start {
+                        continue;
+                    }
+
+                    match self.current_string {
+                        Some(expected)
+                            if expected == index
+                                && !matches!(token_kind, TokenKind::StringStart(_)) =>
+                        {
+                            let delimiter = &config.strings[index].delimiter;
+                            if config.strings[index].doubled
+                                && line[range.end..].starts_with(delimiter.as_str())
+                            {
+                                // The doubled delimiter stands for the delimiter itself
+                                skip_until = range.end + delimiter.len();
+                                continue;
+                            }
+
+                            self.current_string = None;
+                            string_ranges.close(range.end);
+
+                            if !self.docstring_ranges.open_ranges.is_empty() {
+                                if is_blank(config, &line[range.end..]) {
+                                    self.docstring_ranges.close(range.end);
+                                } else {
+                                    // Followed by code, the string is part of an expression
+                                    self.docstring_ranges.open_ranges.pop();
+                                }
+                            }
+                        }
+                        None if !matches!(token_kind, TokenKind::StringEnd(_)) => {
+                            self.current_string = Some(index);
+                            string_ranges.open(range.start);
+
+                            let string = &config.strings[index];
+                            if config.docstrings.contains(&string.delimiter)
+                                && string.prefixes.is_empty()
+                                && line[start..range.start].trim().is_empty()
+                            {
+                                self.docstring_ranges.open(range.start);
+                            }
+                        }
+                        _ => continue,
+                    }
+                    skip_until = range.end;
+                }
+                TokenKind::Heredoc(index) => {
+                    if line_range.contains(range.start)
+                        || multiline_ranges.contains(range.start)
+                        || string_ranges.contains(range.start)
+                    {
+                        continue;
+                    }
+
+                    let heredoc = &config.heredocs[index];
+                    let Some(captures) = heredoc.start.captures_at(line, range.start) else {
+                        continue;
+                    };
+                    let Some(terminator) = captures.name("terminator") else {
+                        continue;
+                    };
+                    let indented = heredoc.indented || captures.name("indent").is_some();
+
+                    // The heredoc's body only begins on the next line, so the rest of this line is still code
+                    string_ranges.push(range);
+                    self.heredocs.push_back(Heredoc {
+                        terminator: terminator.as_str().to_string(),
+                        indented,
+                        trailing_code: heredoc.trailing_code,
+                    });
+                    skip_until = range.end;
+                }
+                TokenKind::InterpolationStart(index) => {
+                    if self.current_string != Some(index) {
+                        continue;
+                    }
+
+                    let escape = escape_of(config, self.current_string);
+                    escape_from = skip_escapes(line, escape_from, range.start, escape);
+                    if escape_from != range.start {
+                        continue;
+                    }
+
+                    // A doubled opener stands for itself, like `{{` in Python's f-strings
+                    let opener = &line[range.start..range.end];
+                    if line[range.end..].starts_with(opener) {
+                        skip_until = range.end + opener.len();
+                        continue;
+                    }
+
+                    // The interpolation's delimiters are part of the code
+                    string_ranges.close(range.start);
+                    self.current_string = None;
+                    self.interpolations.push(Interpolation {
+                        string: index,
+                        depth: 0,
+                    });
+                    skip_until = range.end;
+                }
+                TokenKind::InterpolationEnd(index) | TokenKind::InterpolationNest(index) => {
+                    if line_range.contains(range.start)
+                        || multiline_ranges.contains(range.start)
+                        || self.current_string.is_some()
+                    {
+                        continue;
+                    }
+                    let Some(interpolation) = self
+                        .interpolations
+                        .last_mut()
+                        .filter(|interpolation| interpolation.string == index)
+                    else {
+                        continue;
+                    };
+
+                    if matches!(token_kind, TokenKind::InterpolationNest(_)) {
+                        interpolation.depth += 1;
+                    } else if interpolation.depth > 0 {
+                        interpolation.depth -= 1;
+                    } else {
+                        self.interpolations.pop();
+                        self.current_string = Some(index);
+                        string_ranges.open(range.end);
+                    }
+                    skip_until = range.end;
+                }
+                TokenKind::CharLiteral => {
+                    if line_range.contains(range.start)
+                        || multiline_ranges.contains(range.start)
+                        || string_ranges.contains(range.start)
+                    {
+                        continue;
+                    }
+
+                    // Otherwise, the token is the start of a lifetime or a label, and is part of the code
+                    let delimiter = &line[range.start..range.end];
+                    if let Some(length) = char_literal_len(&line[range.start..], delimiter) {
+                        string_ranges.push(Range::new(range.start, range.start + length));
+                        skip_until = range.start + length;
+                    }
+                }
+                TokenKind::RawString(index) | TokenKind::RawComment(index) => {
+                    if line_range.contains(range.start)
+                        || multiline_ranges.contains(range.start)
+                        || string_ranges.contains(range.start)
+                    {
+                        continue;
+                    }
+
+                    let comment = matches!(token_kind, TokenKind::RawComment(_));
+                    let delimiter = if comment {
+                        &config.raw_comments[index]
+                    } else {
+                        &config.raw_strings[index]
+                    };
+                    let Some(captures) = delimiter.start.captures_at(line, range.start) else {
+                        continue;
+                    };
+                    let mut end = String::new();
+                    captures.expand(&delimiter.end, &mut end);
+
+                    let kept = comment && keeps_comment(config, &line[range.end..]);
+                    let ranges = if comment {
+                        &mut *multiline_ranges
+                    } else {
+                        &mut *string_ranges
+                    };
+                    match line[range.end..].find(end.as_str()) {
+                        Some(index) => {
+                            skip_until = range.end + index + end.len();
+                            ranges.push(Range::new(range.start, skip_until));
+                            if kept {
+                                self.kept_ranges.push(Range::new(range.start, skip_until));
+                            }
+                        }
+                        None => {
+                            skip_until = line.len();
+                            ranges.open(range.start);
+                            if kept {
+                                self.kept_ranges.open(range.start);
+                            }
+                            self.raw_end = Some(RawEnd { end, comment });
+                        }
+                    }
+                }
+                TokenKind::Region(index) => {
+                    if line_range.contains(range.start)
+                        || multiline_ranges.contains(range.start)
+                        || string_ranges.contains(range.start)
+                    {
+                        continue;
+                    }
+
+                    let region = &config.regions[index];
+                    let Some(captures) = region.start.captures_at(line, range.start) else {
+                        continue;
+                    };
+                    let mut end = String::new();
+                    captures.expand(&region.end, &mut end);
+                    let mut lang = String::new();
+                    captures.expand(&region.lang, &mut lang);
+
+                    if config.prose {
+                        multiline_ranges.push(Range::new(start, range.end));
+                    }
+
+                    // The rest of the line is left to the embedded language
+                    self.embedded = Some(Embedded {
+                        end,
+                        own_line: region.own_line,
+                        parser: find_lang(self.embedded_langs, &lang)
+                            .map(|stripper| Box::new(stripper.parser())),
+                    });
+                    return Some(range.end);
+                }
+            }
+        }
+
+        if config.prose {
+            multiline_ranges.push(Range::new(start, line.len()));
+        }
+
+        None
+    }
+}
+
+/// Finds the language embedded within a region, by name or by extension.
+fn find_lang<'a>(langs: &'a IndexMap<String, Stripper>, lang: &str) -> Option<&'a Stripper> {
+    if lang.is_empty() {
+        return None;
+    }
+
+    langs.get(lang).or_else(|| {
+        let extension = lang.to_lowercase();
+        langs
+            .values()
+            .rev()
+            .find(|stripper| stripper.config.extensions.contains(&extension))
+    })
+}
+
+/// The kind of text that a [`Segment`] covers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SegmentKind {
+    Code,
+    Comment,
+    DocComment,
+    String,
+}
+
+/// A run of text of the same kind; `byte_range` indexes into the text given to [`Stripper::segments`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    pub byte_range: std::ops::Range<usize>,
+}
+
+/// Iterator over the segments of a text, see [`Stripper::segments`].
+/// Segments never are empty, and two consecutive segments never share the same kind.
+pub struct Segments<'a> {
+    parser: Parser<'a>,
+    lines: std::str::SplitInclusive<'a, char>,
+    offset: usize,
+    queue: VecDeque<Segment>,
+    current: Option<Segment>,
+}
+
+impl<'a> Segments<'a> {
+    /// Parses the next line and queues its segments, returning false once the text is exhausted.
+    fn next_line(&mut self) -> bool {
+        let Some(line) = self.lines.next() else {
+            return false;
+        };
+        let content = line
+            .strip_suffix('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .unwrap_or(line);
+        let line_ranges = self.parser.parse_line(content);
+
+        // Doc comments come first, so that the comments they overlap with are skipped
+        let mut ranges = line_ranges
+            .docs
+            .iter()
+            .map(|range| (*range, SegmentKind::DocComment))
+            .chain(
+                line_ranges
+                    .comments
+                    .iter()
+                    .map(|range| (*range, SegmentKind::Comment)),
+            )
+            .chain(
+                line_ranges
+                    .strings
+                    .iter()
+                    .map(|range| (*range, SegmentKind::String)),
+            )
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|(range, _)| range.start);
+
+        let mut position = 0;
+        for (range, kind) in ranges {
+            // Ranges continuing onto the next line also cover the line terminator
+            let end = if range.end > content.len() {
+                line.len()
+            } else {
+                range.end
+            };
+            if end <= position {
+                // Nested comments overlap with the comment they are in
+                continue;
+            }
+
+            if range.start > position {
+                self.queue
+                    .push_back(self.segment(SegmentKind::Code, position, range.start));
+            }
+            self.queue
+                .push_back(self.segment(kind, range.start.max(position), end));
+            position = end;
+        }
+        if position < line.len() {
+            self.queue
+                .push_back(self.segment(SegmentKind::Code, position, line.len()));
+        }
+
+        self.offset += line.len();
+        true
+    }
+
+    fn segment(&self, kind: SegmentKind, start: usize, end: usize) -> Segment {
+        Segment {
+            kind,
+            byte_range: (self.offset + start)..(self.offset + end),
+        }
+    }
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = Segment;
+
+    fn next(&mut self) -> Option<Segment> {
+        loop {
+            while let Some(segment) = self.queue.pop_front() {
+                match &mut self.current {
+                    Some(current) if current.kind == segment.kind => {
+                        current.byte_range.end = segment.byte_range.end;
+                    }
+                    current => {
+                        if let Some(previous) = current.replace(segment) {
+                            return Some(previous);
+                        }
+                    }
+                }
+            }
+
+            if !self.next_line() {
+                return self.current.take();
+            }
+        }
+    }
+}
+
+/// Parses the whole input, returning the comments and strings found in each line.
+pub fn parse_input(config: &LangConfig, input: impl BufRead) -> std::io::Result<Vec<LineRanges>> {
+    Stripper::new(config.clone()).parse(input)
+}
+
+/// Writes `input` to `output`, with comments (and strings, unless `config.keep_strings` is set) stripped away.
+/// Prefer [`Stripper::strip`] when processing several inputs with the same config.
+pub fn handle_input(
+    config: &LangConfig,
+    input: impl BufRead,
+    output: impl Write,
+) -> std::io::Result<()> {
+    Stripper::new(config.clone()).strip(input, output)
+}
+
+/// Returns true if a comment whose text after its opener is `text` should be kept, see [`LangConfig::keep_comments_matching`].
+fn keeps_comment(config: &LangConfig, text: &str) -> bool {
+    config
+        .keep_comments_matching
+        .iter()
+        .any(|pattern| pattern.is_match(text))
+}
+
+/// Returns true if `text` holds nothing but whitespace and line comments.
+fn is_blank(config: &LangConfig, text: &str) -> bool {
+    let text = text.trim_start();
+    text.is_empty()
+        || config
+            .line_comments
+            .iter()
+            .any(|comment| text.starts_with(comment.as_str()))
+}
+
+/// Returns the length of the character literal at the start of `text`, delimited by `delimiter`,
+/// or `None` if `text` does not start with one (as is the case for `'a` or `'static` in Rust).
+fn char_literal_len(text: &str, delimiter: &str) -> Option<usize> {
+    let body = &text[delimiter.len()..];
+    let mut chars = body.chars();
+
+    let body_len = match chars.next()? {
+        '\\' => {
+            // Escape sequences, for instance `\n`, `\'`, `\x7f` or `\u{1F600}`
+            let escaped = chars.next()?;
+            let mut len = 1 + escaped.len_utf8();
+
+            if escaped == 'u' && body[len..].starts_with('{') {
+                len += body[len..].find('}')? + 1;
+            } else if escaped == 'x' {
+                len += body[len..]
+                    .chars()
+                    .take(2)
+                    .take_while(char::is_ascii_hexdigit)
+                    .count();
+            }
+
+            len
+        }
+        first if delimiter.starts_with(first) => return None,
+        first => first.len_utf8(),
+    };
+
+    body[body_len..]
+        .starts_with(delimiter)
+        .then_some(2 * delimiter.len() + body_len)
+}
+
+/// Returns the escape token in effect within the given kind of string, or outside of strings if `string` is `None`.
+fn escape_of(config: &LangConfig, string: Option<usize>) -> &str {
+    string
+        .and_then(|index| config.strings[index].escape.as_deref())
+        .or(config.escape.as_deref())
+        .unwrap_or("")
+}
+
+/// Walks `text` from `from`, which is known not to be escaped, up to `index`, stepping over escaped characters.
+/// Returns `index` if the character at `index` isn't escaped, or the index of the first character past it otherwise.
+fn skip_escapes(text: &str, from: usize, index: usize, escape: &str) -> usize {
+    let mut position = from;
+
+    while position < index {
+        if !escape.is_empty() && text[position..].starts_with(escape) {
+            position += escape.len();
+        }
+        position += text[position..].chars().next().map_or(1, char::len_utf8);
+    }
+
+    position
+}
+
+fn collect_blacklist_ranges(line: &str, blacklists: &[String]) -> Vec<Range> {
+    let mut ranges = blacklists
+        .iter()
+        .flat_map(|blacklist| line.match_indices(blacklist))
+        .map(|(start, string)| Range::new(start, start + string.len()))
+        .collect::<Vec<_>>();
+
+    ranges.sort_unstable_by_key(|range| range.start);
+
+    ranges
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|&(i, range)| !ranges.iter().take(i).any(|other| other.overlaps(&range)))
+        .map(|pair| pair.1)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_handle_input(config: LangConfig, input: &'static str, expected: &'static str) {
+        let mut output: Vec<u8> = Vec::new();
+        handle_input(&config, std::io::Cursor::new(input), &mut output).unwrap();
+
+        let output = String::from_utf8(output).expect("handle_input did not return valid UTF-8");
+        assert_eq!(output, expected);
+    }
+
+    fn test_segments(config: LangConfig, input: &'static str, expected: &[(SegmentKind, &str)]) {
+        let stripper = Stripper::new(config);
+        let segments = stripper
+            .segments(input)
+            .map(|segment| (segment.kind, &input[segment.byte_range]))
+            .collect::<Vec<_>>();
+
+        assert_eq!(segments, expected);
+    }
+
+    #[test]
+    fn test_overlap() {
+        fn test_overlap([xmin, xsup]: [usize; 2], [ymin, ysup]: [usize; 2], positive: bool) {
+            assert_eq!(
+                Range::new(xmin, xsup).overlaps(&Range::new(ymin, ysup)),
+                positive,
+                "Range [{xmin}; {xsup}) should{} overlap with [{ymin}; {ysup})",
+                if positive { "" } else { "n't" }
+            );
+
+            assert_eq!(
+                Range::new(ymin, ysup).overlaps(&Range::new(xmin, xsup)),
+                positive,
+                "Range [{ymin}; {ysup}) should{} overlap with [{xmin}; {xsup})",
+                if positive { "" } else { "n't" }
+            );
+        }
+
+        test_overlap([0, 0], [0, 100], false);
+        test_overlap([50, 50], [0, 100], false);
+        test_overlap([100, 100], [0, 100], false);
+        test_overlap([0, 1], [0, 1], true);
+        test_overlap([0, 1], [1, 2], false);
+        test_overlap([1, 2], [0, 1], false);
+        test_overlap([5, 10], [9, 12], true);
+        test_overlap([5, 10], [3, 6], true);
+        test_overlap([5, 10], [10, 12], false);
+        test_overlap([5, 10], [3, 5], false);
+        test_overlap([5, 10], [4, 11], true);
+        test_overlap([5, 10], [3, 12], true);
+    }
+
+    #[test]
+    fn test_noop() {
+        test_handle_input(LangConfig::default(), "\n", "\n");
+
+        test_handle_input(LangConfig::default(), "abc", "abc\n");
+        test_handle_input(LangConfig::default().nested_comments(true), "abc", "abc\n");
+
+        test_handle_input(LangConfig::default(), "abc\n", "abc\n");
+        test_handle_input(
+            LangConfig::default().nested_comments(true),
+            "abc\n",
+            "abc\n",
+        );
+
+        test_handle_input(LangConfig::default(), "abc\ndef", "abc\ndef\n");
+        test_handle_input(
+            LangConfig::default().nested_comments(true),
+            "abc\ndef",
+            "abc\ndef\n",
+        );
+    }
+
+    #[test]
+    fn test_singleline() {
+        let config = LangConfig::default().line_comment("//");
+
+        test_handle_input(config.clone(), "hello // world", "hello \n");
+        test_handle_input(
+            config.clone(),
+            "hello // world\nuntouched",
+            "hello \nuntouched\n",
+        );
+        test_handle_input(config.clone(), "hello // world // hey", "hello \n");
+        test_handle_input(config.clone(), "hello // world\n// hey", "hello \n\n");
+        test_handle_input(config.clone(), "a//b", "a\n");
+    }
+
+    #[test]
+    fn test_multiline() {
+        let config = LangConfig::default().multiline_comment("/*", "*/");
+
+        test_handle_input(config.clone(), "hello /* world", "hello \n");
+        test_handle_input(config.clone(), "hello */ world", "hello */ world\n");
+        test_handle_input(config.clone(), "hello /* world */", "hello \n");
+        test_handle_input(
+            config.clone(),
+            "hello /* world */\na very long line with a lot of text",
+            "hello \na very long line with a lot of text\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "hello /* world\n*/newline",
+            "hello \nnewline\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "hello /* world */ included",
+            "hello  included\n",
+        );
+        test_handle_input(config.clone(), "hello /*/ world */ a", "hello  a\n");
+    }
+
+    #[test]
+    fn test_interactions() {
+        let config = LangConfig::default()
+            .multiline_comment("/*", "*/")
+            .line_comment("//");
+
+        // A multiline comment in a single-line comment should be ignored
+        test_handle_input(
+            config.clone(),
+            "hello // /* world\nincluded",
+            "hello \nincluded\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "hello // /* world\nincluded */",
+            "hello \nincluded */\n",
+        );
+
+        // A single-line comment in a multiline comment should be ignored
+        test_handle_input(config.clone(), "/* // */hello world", "hello world\n");
+        test_handle_input(
+            config.clone(),
+            "/* // */hello world // commented",
+            "hello world \n",
+        );
+
+        // If a single-line comment is merged with a multiline comment, then the latter does not apply
+        test_handle_input(config.clone(), "//* hello */\nworld", "\nworld\n");
+        test_handle_input(config.clone(), "/*\n*//world", "\n/world\n");
+
+        let alt_config = LangConfig::default()
+            .multiline_comment("/-", "-/")
+            .line_comment("--");
+
+        // Equivalently, if a multi-line comment is merged with a single line comment, then the latter does not apply
+        test_handle_input(alt_config.clone(), "/-- hello -/\nworld", "\nworld\n");
+        test_handle_input(alt_config.clone(), "/-\n--/world", "\nworld\n");
+    }
+
+    #[test]
+    fn test_nested_comments() {
+        let config = LangConfig::default()
+            .multiline_comment("/*", "*/")
+            .multiline_comment("(-", "-)")
+            .nested_comments(true);
+
+        test_handle_input(config.clone(), "/* /* abc */ def */hello", "hello\n");
+        test_handle_input(config.clone(), "/* /* abc\n */ def\n*/hello", "\n\nhello\n");
+
+        test_handle_input(config.clone(), "/* (- abc */ def -)hello", "\n");
+        test_handle_input(config.clone(), "/* -) abc */ def", " def\n");
+    }
+
+    #[test]
+    fn test_string() {
+        let config = LangConfig::default()
+            .multiline_comment("/*", "*/")
+            .line_comment("//")
+            .string("\"")
+            .string("'");
+
+        test_handle_input(config.clone(), "let a = \"hello\";", "let a = \"…\";\n");
+        test_handle_input(
+            config.clone(),
+            "let a = \"hello\";\na very long line with a lot of text",
+            "let a = \"…\";\na very long line with a lot of text\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "let a = \"hello // world\";",
+            "let a = \"…\";\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "let a = \"Jack'o'lantern\";",
+            "let a = \"…\";\n",
+        );
+        test_handle_input(config.clone(), "let a = 'hello';", "let a = \"…\";\n");
+        test_handle_input(config.clone(), "a\"b\"c/* d */e", "a\"…\"ce\n");
+        test_handle_input(
+            config.clone(),
+            "let a = 'hello', 'world';",
+            "let a = \"…\", \"…\";\n",
+        );
+
+        test_handle_input(
+            config.clone(),
+            "let a = \"hello /* world \";",
+            "let a = \"…\";\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "let a = \"hello /* world \";\n*/ this is // normal",
+            "let a = \"…\";\n*/ this is \n",
+        );
+    }
+
+    #[test]
+    fn test_blacklist() {
+        let config = LangConfig::default().string("'").blacklist("\\'");
+
+        test_handle_input(config.clone(), "let a = '\\'';", "let a = '…';\n");
+        test_handle_input(
+            config.clone(),
+            "let a = 'hello \\' world';",
+            "let a = '…';\n",
+        );
+
+        let config = LangConfig::default()
+            .string("'")
+            .blacklist("\\\\")
+            .blacklist("\\'");
+
+        test_handle_input(config.clone(), "let a = '\\\\\\'';", "let a = '…';\n");
+        test_handle_input(config.clone(), "let a = '\\\\';", "let a = '…';\n");
+    }
+
+    #[test]
+    fn test_escapes() {
+        let config = LangConfig::default()
+            .line_comment("//")
+            .string("\"")
+            .string(StringConfig::new("'").escape(""))
+            .string(StringConfig::new("`").interpolation("${", "}"))
+            .escape("\\");
+
+        test_handle_input(config.clone(), r#""a\"b" // c"#, "\"…\" \n");
+        test_handle_input(config.clone(), r#""a\\" // c"#, "\"…\" \n");
+        test_handle_input(config.clone(), r#""\\\"" c"#, "\"…\" c\n");
+        test_handle_input(config.clone(), "\"a\\\nb\" c", "\"…\"\n\"…\" c\n");
+        test_handle_input(config.clone(), r"'a\' // c", "\"…\" \n");
+        test_handle_input(config.clone(), r#"a \" b // c"#, "a \\\" b \n");
+        test_handle_input(config.clone(), r"`\${a}` b", "\"…\" b\n");
+        test_handle_input(config.clone(), r"`\\${a}` b", "\"…\"${a}\"…\" b\n");
+
+        let sql = LangConfig::default()
+            .line_comment("--")
+            .string(StringConfig::new("'").doubled(true));
+
+        test_handle_input(sql.clone(), "'it''s' -- c", "'…' \n");
+        test_handle_input(sql.clone(), "'' a '''' b -- c", "'…' a '…' b \n");
+        test_handle_input(sql.clone(), r"'a\' b", "'…' b\n");
+        test_handle_input(sql.clone(), "'a''\n''' -- c", "'…'\n'…' \n");
+    }
+
+    #[test]
+    fn test_mask() {
+        let config = LangConfig::default()
+            .line_comment("//")
+            .multiline_comment("/*", "*/")
+            .string("\"")
+            .mask(true);
+
+        test_handle_input(config.clone(), "a /* b */ c // d", "a         c     \n");
+        test_handle_input(config.clone(), "a = \"é\";\t// ü", "a =     ;\t     \n");
+        test_handle_input(config.clone(), "a /*\tb\n*/ c", "a   \t \n   c\n");
+        test_handle_input(
+            config.clone().keep_strings(true),
+            "a(\"b\") /* c */",
+            "a(\"b\")        \n",
+        );
+    }
+
+    #[test]
+    fn test_only() {
+        let config = LangConfig::default()
+            .line_comment("//")
+            .multiline_comment("/*", "*/")
+            .string("\"");
+
+        let comments = config.clone().only(Some(Only::Comments));
+        test_handle_input(
+            comments.clone(),
+            "a(\"b\") /* c */ d // e\nf /*\ng */",
+            "/* c */// e\n/*\ng */\n",
+        );
+        test_handle_input(
+            comments.mask(true),
+            "a /* b */ c // d",
+            "  /* b */   // d\n",
+        );
+
+        let strings = config.only(Some(Only::Strings));
+        test_handle_input(
+            strings.clone(),
+            "a(\"b\", \"c // d\") // \"e\"\nf",
+            "\"b\"\"c // d\"\n\n",
+        );
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let config = LangConfig::default()
+            .line_comment("//")
+            .multiline_comment("/*", "*/")
+            .doc_comment("///")
+            .doc_comment("/**")
+            .string("\"");
+
+        test_handle_input(config.clone(), "/// a\nb /** c */ d // e", "\nb  d \n");
+
+        let keep_docs = config.clone().keep_doc_comments(true);
+        test_handle_input(
+            keep_docs.clone(),
+            "/// a\nb /** c */ d // e /// f",
+            "/// a\nb /** c */ d \n",
+        );
+        test_handle_input(
+            keep_docs.clone(),
+            "/**\n * \"a\" // b\n */ c /* d */",
+            "/**\n * \"a\" // b\n */ c \n",
+        );
+        test_handle_input(keep_docs.clone(), "a /**/ b /* /** */ c", "a  b  c\n");
+
+        let nested = keep_docs.nested_comments(true);
+        test_handle_input(nested, "/** a /* b */ c */ d", "/** a /* b */ c */ d\n");
+
+        test_handle_input(
+            config.only(Some(Only::Docs)),
+            "a /** b */ c // d\n/// e",
+            "/** b */\n/// e\n",
+        );
+    }
+
+    #[test]
+    fn test_keep_comments_matching() {
+        let config = LangConfig::default()
+            .line_comment("//")
+            .multiline_comment("/*", "*/")
+            .doc_comment("///")
+            .string("\"")
+            .keep_comment_matching(r"^\s*SAFETY:")
+            .keep_comment_matching(r"^\s*eslint-disable");
+
+        test_handle_input(
+            config.clone(),
+            "// SAFETY: see // below\na // eslint-disable-line\nb // c SAFETY:",
+            "// SAFETY: see // below\na // eslint-disable-line\nb \n",
+        );
+        test_handle_input(
+            config.clone(),
+            "/* SAFETY: \"a\"\n   b */ c /* d */ e",
+            "/* SAFETY: \"a\"\n   b */ c  e\n",
+        );
+        test_handle_input(config.clone(), "/// SAFETY: a\n/// b", "/// SAFETY: a\n\n");
+        test_handle_input(
+            config.clone().mask(true),
+            "a /* b */ c // SAFETY: d",
+            "a         c // SAFETY: d\n",
+        );
+
+        let nested = config.clone().nested_comments(true);
+        test_handle_input(
+            nested,
+            "/* SAFETY: /* a */ b */ c /* /* SAFETY: d */ */",
+            "/* SAFETY: /* a */ b */ c \n",
+        );
+
+        let raw = LangConfig::default()
+            .raw_comment(r"--\[(=*)\[", "]$1]")
+            .keep_comment_matching(r"^\s*SAFETY:");
+        test_handle_input(
+            raw,
+            "a --[[ SAFETY: b\n]] c --[[ d ]]",
+            "a --[[ SAFETY: b\n]] c \n",
+        );
+    }
+
+    #[test]
+    fn test_docstrings() {
+        let config = LangConfig::default()
+            .line_comment("#")
+            .string("\"\"\"")
+            .string("\"")
+            .string(StringConfig::new("\"\"\"").prefix("f"))
+            .docstring("\"\"\"")
+            .keep_strings(true);
+
+        test_handle_input(
+            config.clone(),
+            "def f():\n    \"\"\"Doc\n    string.\"\"\"  # a\n    return \"\"\"b\"\"\"",
+            "def f():\n    \n  \n    return \"\"\"b\"\"\"\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "\"\"\"a\"\"\".join(b)\n\"c\"\nf\"\"\"d\"\"\"",
+            "\"\"\"a\"\"\".join(b)\n\"c\"\nf\"\"\"d\"\"\"\n",
+        );
+        test_handle_input(
+            config.clone().keep_doc_comments(true),
+            "\"\"\"a\"\"\"\nx = \"\"\"b\"\"\"",
+            "\"\"\"a\"\"\"\nx = \"\"\"b\"\"\"\n",
+        );
+        test_handle_input(
+            config.keep_strings(false),
+            "\"\"\"a\"\"\"\nx = \"\"\"b\"\"\"",
+            "\nx = \"\"\"…\"\"\"\n",
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let config = LangConfig::default()
+            .line_comment("//")
+            .multiline_comment("/*", "*/")
+            .string("\"")
+            .conditionals(ConditionalConfig::new(
+                r"^\s*#\s*(?:el)?if\s+0\b",
+                r"^\s*#\s*if",
+                r"^\s*#\s*el(?:se|if)\b",
+                r"^\s*#\s*endif\b",
+            ));
+
+        test_handle_input(
+            config.clone(),
+            "a\n#if 0\nb \"c\"\n#ifdef D\ne\n#endif\nf\n#endif\ng",
+            "a\n#if 0\n\n\n\n\n\n#endif\ng\n",
+        );
         test_handle_input(
             config.clone(),
-            "/* // */hello world // commented",
-            "hello world \n",
+            "#if 0\na\n#else\nb\n#endif\n#ifdef C\nd\n#else\ne\n#endif",
+            "#if 0\n\n#else\nb\n#endif\n#ifdef C\nd\n#else\ne\n#endif\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "#if 0\n/*\n#endif\n*/\n#endif\na",
+            "#if 0\n\n\n\n#endif\na\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "#if 0\nwe don't need this\n#endif\nint x; // a",
+            "#if 0\n\n#endif\nint x; \n",
+        );
+        test_handle_input(
+            config.clone(),
+            "#if 0\na\n#elif 0 // b\nc\n#elif 0x1\nd\n#endif\n#if 0\ne\n#elif FOO\nf\n#else\ng\n#endif\n#ifdef H\ni\n#elif 0\nj\n#if 0\n#else\n#endif\n#endif",
+            "#if 0\n\n#elif 0 \n\n#elif 0x1\nd\n#endif\n#if 0\n\n#elif FOO\nf\n#else\ng\n#endif\n#ifdef H\ni\n#elif 0\n\n\n\n\n#endif\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "/*\n#if 0\n*/\na\n#ifdef B\n#endif\nc",
+            "\n\n\na\n#ifdef B\n#endif\nc\n",
         );
+        test_handle_input(
+            config.mask(true),
+            "#if 0\nab\n#endif",
+            "#if 0\n  \n#endif\n",
+        );
+    }
 
-        // If a single-line comment is merged with a multiline comment, then the latter does not apply
-        test_handle_input(config.clone(), "//* hello */\nworld", "\nworld\n");
-        test_handle_input(config.clone(), "/*\n*//world", "\n/world\n");
+    #[test]
+    fn test_strip_blocks() {
+        let config = LangConfig::default()
+            .line_comment("//")
+            .string("\"")
+            .strip_block(StripBlockConfig::new(r"#\[cfg\(test\)\]").terminator(";"));
 
-        let alt_config = LangConfig::default()
-            .multiline_comment("/-", "-/")
-            .line_comment("--");
+        test_handle_input(
+            config.clone(),
+            "fn a() {}\n#[cfg(test)]\nmod tests {\n    // }\n    fn b() { \"}\" }\n}\nfn c() {}",
+            "fn a() {}\n\n\n\n\n\nfn c() {}\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "a #[cfg(test)] mod b { c } d #[cfg(test)] mod e { f } g",
+            "a  d  g\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "#[cfg(test)] use a;\nfn b() {}\n// #[cfg(test)]\nfn c() {}",
+            "#[cfg(test)] use a;\nfn b() {}\n\nfn c() {}\n",
+        );
+        test_handle_input(
+            config.clone().keep_strings(true).mask(true),
+            "a #[cfg(test)] { \"b\" }",
+            "a                     \n",
+        );
+        test_handle_input(
+            config,
+            "#[cfg(test)] mod t { fn a() { \"é\"; } }\nb\n#[cfg(test)] mod c {\n    // ü }\n}\nd",
+            "\nb\n\n\n\nd\n",
+        );
+    }
 
-        // Equivalently, if a multi-line comment is merged with a single line comment, then the latter does not apply
-        test_handle_input(alt_config.clone(), "/-- hello -/\nworld", "\nworld\n");
-        test_handle_input(alt_config.clone(), "/-\n--/world", "\nworld\n");
+    #[test]
+    fn test_indented_strip_blocks() {
+        let config = LangConfig::default()
+            .line_comment("#")
+            .string("\"\"\"")
+            .string("\"")
+            .strip_block(StripBlockConfig::new(r"\bdef test_").indented(true))
+            .strip_block(StripBlockConfig::new(r"^if __name__ == ").indented(true));
+
+        test_handle_input(
+            config.clone(),
+            "def a():\n    pass\ndef test_b():\n    x = \"\"\"\nc\n\"\"\"\n\n# d\n    e\ndef f(): pass",
+            "def a():\n    pass\n\n\n\n\n\n\n\ndef f(): pass\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "class A:\n    def test_b(self): pass\n    def c(self):\n        def test_d():\n            e\n        f",
+            "class A:\n    \n    def c(self):\n        \n\n        f\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "\"def test_a():\"\n    b\nif __name__ == \"__main__\":\n    main()",
+            "\"\"\"…\"\"\"\n    b\n\n\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "def test_a(\n    x: dict[str, int] = {\"b\": 1},  # c:\n):\n    secret = 1\nd",
+            "\n\n\n\nd\n",
+        );
     }
 
     #[test]
-    fn test_nested_comments() {
+    fn test_generated_markers() {
+        let config = LangConfig::default()
+            .line_comment("//")
+            .string("\"")
+            .generated_marker(r"@generated\b");
+
+        test_handle_input(config.clone(), "// @generated\na\n\"b\"", "\n\n\n");
+        test_handle_input(config.clone(), "a\n// @generatedd", "a\n\n");
+        test_handle_input(
+            config.clone(),
+            "a = \"@generated\" // b\nc",
+            "a = \"…\" \nc\n",
+        );
+        test_handle_input(
+            config.clone().mask(true),
+            "a // @generated\nbc",
+            "               \n  \n",
+        );
+
+        let late = format!("{}// @generated", "a\n".repeat(GENERATED_MARKER_LINES));
+        let expected = format!("{}\n", "a\n".repeat(GENERATED_MARKER_LINES));
+        let mut output = Vec::new();
+        handle_input(&config, late.as_bytes(), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+        let ranges = parse_input(&config, "// @generated\na".as_bytes()).unwrap();
+        assert!(ranges[1].is_stripped(0, &config));
+
+        let markdown = LangConfig::default()
+            .prose(true)
+            .region(RegionConfig::new(r"^```(?P<lang>\w*)$", "```", "$lang").own_line(true))
+            .generated_marker(r"@generated\b")
+            .embedded_lang("rust", config);
+        test_handle_input(
+            markdown,
+            "# Tool\n\nIts output is marked with @generated.\n\n```rust\nlet a = 1; // b\n```",
+            "\n\n\n\n\nlet a = 1; \n\n",
+        );
+    }
+
+    #[test]
+    fn test_hidden_sections() {
         let config = LangConfig::default()
+            .line_comment("//")
             .multiline_comment("/*", "*/")
-            .multiline_comment("(-", "-)")
-            .nested_comments(true);
+            .string("\"")
+            .hidden_section(HiddenSectionConfig::new(r"\bregion\b", r"\bendregion\b"))
+            .hidden_section(HiddenSectionConfig::new(
+                r"BEGIN GENERATED",
+                r"END GENERATED",
+            ));
 
-        test_handle_input(config.clone(), "/* /* abc */ def */hello", "hello\n");
-        test_handle_input(config.clone(), "/* /* abc\n */ def\n*/hello", "\n\nhello\n");
+        test_handle_input(
+            config.clone(),
+            "a\n// region: vendored\nb\n// region: nested\nc\n// endregion\nd\n// endregion\ne",
+            "a\n\n\n\n\n\n\n\ne\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "a \"// region\"\nb /* BEGIN GENERATED */ c\nd // endregion\n/* END GENERATED */ e",
+            "a \"…\"\nb \n\n e\n",
+        );
+        test_handle_input(
+            config.mask(true),
+            "// region\nab\n// endregion\nc",
+            "         \n  \n            \nc\n",
+        );
+    }
 
-        test_handle_input(config.clone(), "/* (- abc */ def -)hello", "\n");
-        test_handle_input(config.clone(), "/* -) abc */ def", " def\n");
+    #[test]
+    fn test_annotations() {
+        let config = LangConfig::default()
+            .line_comment("//")
+            .multiline_comment("/*", "*/")
+            .string("\"")
+            .annotation(AnnotationConfig::new(r"#!?\[").brackets("[", "]"))
+            .annotation(AnnotationConfig::new(r"@[A-Za-z_][\w.]*"));
+
+        let input =
+            "#[derive(Debug)] struct A;\n@JsonProperty(\"(\") /* ) */ int b;\n@Override\nc(d)";
+        test_handle_input(
+            config.clone(),
+            input,
+            "#[derive(Debug)] struct A;\n@JsonProperty(\"…\")  int b;\n@Override\nc(d)\n",
+        );
+
+        let remove = config.remove_annotations(true);
+        test_handle_input(remove.clone(), input, " struct A;\n  int b;\n\nc(d)\n");
+        test_handle_input(
+            remove.clone(),
+            "#[cfg(all(\n    a, // ]\n    b = \"]\",\n))]\nfn c() {}\n\"@d\" e@f",
+            "\n\n\n\nfn c() {}\n\"…\" e\n",
+        );
+        test_handle_input(
+            remove.clone().keep_strings(true).mask(true),
+            "@A(\"b\") c",
+            "        c\n",
+        );
+        test_handle_input(remove, "@A(\"héllo\") b\n#[doc = \"café\"] c", " b\n c\n");
     }
 
     #[test]
-    fn test_string() {
+    fn test_segment_iterator() {
+        use SegmentKind::*;
+
         let config = LangConfig::default()
             .multiline_comment("/*", "*/")
+            .line_comment("//")
+            .string("\"");
+
+        test_segments(config.clone(), "", &[]);
+        test_segments(config.clone(), "abc\ndef", &[(Code, "abc\ndef")]);
+        test_segments(
+            config.clone(),
+            "a /* b\nc */ d // e\r\nf",
+            &[
+                (Code, "a "),
+                (Comment, "/* b\nc */"),
+                (Code, " d "),
+                (Comment, "// e"),
+                (Code, "\r\nf"),
+            ],
+        );
+        test_segments(
+            config.clone(),
+            "\"a\"/**/\"b\nc\"",
+            &[(String, "\"a\""), (Comment, "/**/"), (String, "\"b\nc\"")],
+        );
+
+        let nested_config = config.clone().nested_comments(true);
+        test_segments(
+            nested_config,
+            "a /* /* b */\n */ c",
+            &[(Code, "a "), (Comment, "/* /* b */\n */"), (Code, " c")],
+        );
+
+        let doc_config = config.doc_comment("///").doc_comment("/**");
+        test_segments(
+            doc_config,
+            "/// a\nb /** c */ /* d */",
+            &[
+                (DocComment, "/// a"),
+                (Code, "\nb "),
+                (DocComment, "/** c */"),
+                (Code, " "),
+                (Comment, "/* d */"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_char_literals() {
+        let config = LangConfig::default()
             .line_comment("//")
             .string("\"")
-            .string("'");
+            .char_literal("'")
+            .blacklist("\\\"")
+            .blacklist("\\\\");
 
-        test_handle_input(config.clone(), "let a = \"hello\";", "let a = \"…\";\n");
         test_handle_input(
             config.clone(),
-            "let a = \"hello\";\na very long line with a lot of text",
-            "let a = \"…\";\na very long line with a lot of text\n",
+            "fn f<'a>(x: &'a str) -> &'static str // hi",
+            "fn f<'a>(x: &'a str) -> &'static str \n",
         );
+        test_handle_input(config.clone(), "let c = 'x';", "let c = \"…\";\n");
         test_handle_input(
             config.clone(),
-            "let a = \"hello // world\";",
-            "let a = \"…\";\n",
+            "let c = '\"'; \"a\"",
+            "let c = \"…\"; \"…\"\n",
         );
+        test_handle_input(config.clone(), "let c = '/'; // a", "let c = \"…\"; \n");
         test_handle_input(
             config.clone(),
-            "let a = \"Jack'o'lantern\";",
-            "let a = \"…\";\n",
+            "['\\n', '\\'', '\\\\']",
+            "[\"…\", \"…\", \"…\"]\n",
         );
-        test_handle_input(config.clone(), "let a = 'hello';", "let a = \"…\";\n");
         test_handle_input(
             config.clone(),
-            "let a = 'hello', 'world';",
-            "let a = \"…\", \"…\";\n",
+            "['\\x7f', '\\u{1F600}', 'é']",
+            "[\"…\", \"…\", \"…\"]\n",
         );
-
         test_handle_input(
             config.clone(),
-            "let a = \"hello /* world \";",
-            "let a = \"…\";\n",
+            "'outer: loop { break 'outer; }",
+            "'outer: loop { break 'outer; }\n",
         );
+        test_handle_input(config.clone(), "\"it's\" 'a'", "\"…\" \"…\"\n");
+    }
+
+    #[test]
+    fn test_raw_strings() {
+        let config = LangConfig::default()
+            .line_comment("//")
+            .multiline_comment("/*", "*/")
+            .string("\"")
+            .raw_string(r#"\br(#*)""#, "\"$1")
+            .raw_string(r#"\bR"([^()\\\s]{0,16})\("#, ")$1\"")
+            .blacklist("\\\"")
+            .blacklist("\\\\");
+
+        test_handle_input(config.clone(), "r\"a // b\" c", "\"…\" c\n");
+        test_handle_input(config.clone(), "r#\"\"// a\"# b", "\"…\" b\n");
+        test_handle_input(config.clone(), "r##\"{\"a\": \"#\"}\"## // b", "\"…\" \n");
+        test_handle_input(config.clone(), "r\"\\\" a", "\"…\" a\n");
         test_handle_input(
             config.clone(),
-            "let a = \"hello /* world \";\n*/ this is // normal",
-            "let a = \"…\";\n*/ this is \n",
+            "a = r#\"\n/* \"\n\"# b\nc",
+            "a = \"…\"\n\"…\"\n\"…\" b\nc\n",
         );
+        test_handle_input(config.clone(), "R\"xyz(a)\" // )xyz\" b", "\"…\" b\n");
+        test_handle_input(config.clone(), "R\"(\\)\" c", "\"…\" c\n");
+        test_handle_input(config.clone(), "bar\"a\" r", "bar\"…\" r\n");
     }
 
     #[test]
-    fn test_blacklist() {
-        let config = LangConfig::default().string("'").blacklist("\\'");
+    fn test_interpolation() {
+        let config = LangConfig::default()
+            .line_comment("//")
+            .multiline_comment("/*", "*/")
+            .string("\"")
+            .string(StringConfig::new("`").interpolation("${", "}"))
+            .blacklist("\\$")
+            .blacklist("\\\\");
 
-        test_handle_input(config.clone(), "let a = '\\'';", "let a = '…';\n");
         test_handle_input(
             config.clone(),
-            "let a = 'hello \\' world';",
-            "let a = '…';\n",
+            "`hi ${user.name}` // c",
+            "\"…\"${user.name}\"…\" \n",
+        );
+        test_handle_input(
+            config.clone(),
+            "`${ {a: \"}\"}.a /* } */ }`",
+            "\"…\"${ {a: \"…\"}.a  }\"…\"\n",
+        );
+        test_handle_input(
+            config.clone(),
+            "`a ${ `b ${c}` } d`",
+            "\"…\"${ \"…\"${c}\"…\" }\"…\"\n",
+        );
+        test_handle_input(config.clone(), "`\\${a}` b", "\"…\" b\n");
+        test_handle_input(config.clone(), "\"${a}\" b", "\"…\" b\n");
+        test_handle_input(
+            config.clone(),
+            "`a\n${\nb\n}\nc` d",
+            "\"…\"\n${\nb\n}\"…\"\n\"…\" d\n",
         );
 
-        let config = LangConfig::default()
+        let python = LangConfig::default()
+            .line_comment("#")
+            .string("\"")
+            .string(StringConfig::new("\"").prefix("f").interpolation("{", "}"));
+
+        test_handle_input(
+            python.clone(),
+            "f\"{x} {y[{1: 2}[1]]}\" # c",
+            "\"…\"{x}\"…\"{y[{1: 2}[1]]}\"…\" \n",
+        );
+        test_handle_input(python.clone(), "\"{x}\" {y}", "\"…\" {y}\n");
+        test_handle_input(python.clone(), "f\"{{a}} {{{b}}}\" # c", "\"…\"{b}\"…\" \n");
+        test_handle_input(
+            python.clone(),
+            "f\"{x:{w}}\" # c\ny = 1 # d",
+            "\"…\"{x:{w}}\"…\" \ny = 1 \n",
+        );
+        test_handle_input(
+            python,
+            "f\"{v:>{n}}}}\" # c\ny = 1 # d",
+            "\"…\"{v:>{n}}\"…\" \ny = 1 \n",
+        );
+    }
+
+    #[test]
+    fn test_heredocs() {
+        let shell = LangConfig::default()
+            .line_comment("#")
+            .string("\"")
             .string("'")
-            .blacklist("\\\\")
-            .blacklist("\\'");
+            .heredoc(HeredocConfig::new(
+                r#"<<(?P<indent>-)?\s*['"\\]?(?P<terminator>[A-Za-z_][A-Za-z0-9_]*)['"]?"#,
+            ))
+            .region(RegionConfig::new(r"\$?\(\(", "))", ""))
+            .blacklist("<<<");
 
-        test_handle_input(config.clone(), "let a = '\\\\\\'';", "let a = '…';\n");
-        test_handle_input(config.clone(), "let a = '\\\\';", "let a = '…';\n");
+        test_handle_input(
+            shell.clone(),
+            "cat <<EOF > out # c\nSELECT '#';\n  EOF\nEOF\necho 'a'",
+            "cat \"…\" > out \n\"…\"\n\"…\"\n\"…\"\necho \"…\"\n",
+        );
+        test_handle_input(
+            shell.clone(),
+            "cat <<-'EOF'\n\ta\n\tEOF\nb",
+            "cat \"…\"\n\"…\"\n\"…\"\nb\n",
+        );
+        test_handle_input(
+            shell.clone(),
+            "f <<A <<\"B\"\na\nA\nb\nB\nc",
+            "f \"…\" \"…\"\n\"…\"\n\"…\"\n\"…\"\n\"…\"\nc\n",
+        );
+        test_handle_input(shell.clone(), "cat <<<EOF # c\nd", "cat <<<EOF \nd\n");
+        test_handle_input(shell.clone(), "echo '<<EOF'\nd", "echo \"…\"\nd\n");
+        test_handle_input(
+            shell.clone(),
+            "echo $(( 1 << n )) # a\nx=$((a<<b))\n(( y <<= 16#f ))\nc # d",
+            "echo $(( 1 << n )) \nx=$((a<<b))\n(( y <<= 16#f ))\nc \n",
+        );
+
+        let php = LangConfig::default()
+            .line_comment("//")
+            .string("\"")
+            .heredoc(
+                HeredocConfig::new(r#"<<<\s*['"]?(?P<terminator>[A-Za-z_]\w*)['"]?"#)
+                    .indented(true)
+                    .trailing_code(true),
+            );
+
+        test_handle_input(
+            php.clone(),
+            "f(<<<EOT\n  a\n  EOTX\n  EOT, 1); // c",
+            "f(\"…\"\n\"…\"\n\"…\"\n\"…\", 1); \n",
+        );
+
+        let ruby = LangConfig::default()
+            .line_comment("#")
+            .string("\"")
+            .heredoc(HeredocConfig::new(
+                r#"\B<<(?P<indent>[-~])?['"`]?(?P<terminator>[A-Za-z_]\w*)['"`]?"#,
+            ))
+            .blacklist("class <<")
+            .blacklist("class<<");
+
+        test_handle_input(
+            ruby.clone(),
+            "x = <<~SQL # a\n  b\n  SQL\nc # d",
+            "x = \"…\" \n\"…\"\n\"…\"\nc \n",
+        );
+        test_handle_input(
+            ruby.clone(),
+            "f(<<A)\nb # c\nA\nlines<<line # d\ne # f",
+            "f(\"…\")\n\"…\"\n\"…\"\nlines<<line \ne \n",
+        );
+        test_handle_input(
+            ruby,
+            "class <<self # a\n  def b; end # c\nend\nclass << self\nend # d",
+            "class <<self \n  def b; end \nend\nclass << self\nend \n",
+        );
+    }
+
+    #[test]
+    fn test_raw_comments() {
+        let lua = LangConfig::default()
+            .line_comment("--")
+            .string("\"")
+            .raw_string(r"\[(=*)\[", "]$1]")
+            .raw_comment(r"--\[(=*)\[", "]$1]");
+
+        test_handle_input(lua.clone(), "a --[[ b ]] c -- d", "a  c \n");
+        test_handle_input(lua.clone(), "a --[==[ ]] ]=] ]==] c", "a  c\n");
+        test_handle_input(
+            lua.clone(),
+            "a --[=[\n]] \"\n]=] b [[ -- ]] c",
+            "a \n\n b \"…\" c\n",
+        );
+        test_handle_input(
+            lua.clone(),
+            "a = [==[\n]]\n]==] -- b",
+            "a = \"…\"\n\"…\"\n\"…\" \n",
+        );
+        test_handle_input(lua.clone(), "a = \"--[[\" b", "a = \"…\" b\n");
+        test_handle_input(lua.clone(), "--[ a\nb", "\nb\n");
+    }
+
+    #[test]
+    fn test_regions() {
+        let js = LangConfig::default()
+            .line_comment("//")
+            .multiline_comment("/*", "*/")
+            .string("\"");
+        let html = LangConfig::default()
+            .multiline_comment("<!--", "-->")
+            .region(RegionConfig::new(r"<script\b[^>]*>", "</script>", "js"))
+            .embedded_lang("js", js.clone());
+
+        test_handle_input(
+            html.clone(),
+            "<p>a // b</p><script>c // d</script> // e",
+            "<p>a // b</p><script>c </script> // e\n",
+        );
+        test_handle_input(
+            html.clone(),
+            "<!-- <script> -->a /* b\n<script type=\"x\">\nc /* d\n*/ \"e\" </script><!-- f -->",
+            "a /* b\n<script type=\"x\">\nc \n \"…\" </script>\n",
+        );
+        test_handle_input(
+            html.clone(),
+            "<script>a = \"</script>\" <!-- b -->",
+            "<script>a = \"…\"</script>\" \n",
+        );
+
+        let unresolved = LangConfig::default()
+            .line_comment("#")
+            .region(RegionConfig::new("<script>", "</script>", "js"));
+        test_handle_input(
+            unresolved,
+            "<script>a // b # c</script> # d",
+            "<script>a // b # c</script> \n",
+        );
+    }
+
+    #[test]
+    fn test_prose() {
+        let rust = LangConfig::default()
+            .extension("rs")
+            .line_comment("//")
+            .string("\"");
+        let markdown = LangConfig::default()
+            .prose(true)
+            .region(
+                RegionConfig::new(
+                    r"^ {0,3}(?P<fence>`{3,}|~{3,})\s*(?P<lang>[\w+#-]*).*$",
+                    "$fence",
+                    "$lang",
+                )
+                .own_line(true),
+            )
+            .region(RegionConfig::new(r"^(?: {4}|\t)", "", ""))
+            .embedded_lang("rust", rust);
+
+        test_handle_input(
+            markdown.clone(),
+            "# Title\n\n```rust\nlet a = \"b\"; // c\n```\nSome `code`.\n",
+            "\n\n\nlet a = \"…\"; \n\n\n",
+        );
+        test_handle_input(
+            markdown.clone(),
+            "```rust\nlet a = \"```\"; // b\nc // d\n  ```  \ne // f\n",
+            "\nlet a = \"…\"; \nc \n\n\n",
+        );
+        test_handle_input(
+            markdown.clone(),
+            "```rust\na // b\n``\n~~~~\nc // d\n`````\ne // f\n",
+            "\na \n``\n~~~~\nc \n\n\n",
+        );
+        test_handle_input(
+            markdown.clone(),
+            "~~~rs ignore\nf() // a\n~~~\n````toml\na = 1 # b\n```\n````\n",
+            "\nf() \n\n\na = 1 # b\n```\n\n",
+        );
+        test_handle_input(
+            markdown.clone(),
+            "Text:\n\n    let a = 1; // b\n\tc\nd",
+            "\n\nlet a = 1; // b\nc\n\n",
+        );
+    }
+
+    #[test]
+    fn test_dollar_quotes() {
+        let sql = LangConfig::default()
+            .line_comment("--")
+            .multiline_comment("/*", "*/")
+            .string(StringConfig::new("'").doubled(true))
+            .string(
+                StringConfig::new("'")
+                    .prefix("E")
+                    .escape("\\")
+                    .doubled(true),
+            )
+            .raw_string(r"\B\$([A-Za-z_]\w*)?\$", "$$${1}$$")
+            .nested_comments(true);
+
+        test_handle_input(sql.clone(), "$$it's$$ -- c", "'…' \n");
+        test_handle_input(
+            sql.clone(),
+            "AS $body$\n'a' -- $$\n$body$; -- c",
+            "AS '…'\n'…'\n'…'; \n",
+        );
+        test_handle_input(sql.clone(), "E'a\\'b' || 'c\\' d", "'…' || '…' d\n");
+        test_handle_input(sql.clone(), "$1 /* a /* b */ c */ t$x$", "$1  t$x$\n");
     }
 }
@@ -0,0 +1,109 @@
+use gumdrop::Options;
+
+use just_the_code::{Only, Pattern};
+
+#[derive(Options)]
+pub struct RuntimeConfig {
+    #[options(
+        free,
+        help = "The file to process, `search PATTERN [PATHS...]` to search a directory tree, or `filter-rg` to filter the output of `rg --json` from stdin."
+    )]
+    pub args: Vec<String>,
+
+    pub help: bool,
+
+    #[options(help = "When set, the file will be read from stdin instead.")]
+    pub read_stdin: bool,
+
+    #[options(
+        help = "When set, overrides which language to interpret the file as. Not supported in search mode."
+    )]
+    pub language: Option<String>,
+
+    #[options(
+        short = "s",
+        help = "When set, strings will be kept in the output file, ignoring the behaviour specified by the config file."
+    )]
+    pub keep_strings: bool,
+
+    #[options(
+        short = "S",
+        help = "When set, strings will be removed from the output file, ignoring the behaviour specified by the config file."
+    )]
+    pub remove_strings: bool,
+
+    #[options(
+        short = "d",
+        help = "When set, doc comments will be kept in the output file, ignoring the behaviour specified by the config file."
+    )]
+    pub keep_doc_comments: bool,
+
+    #[options(
+        short = "D",
+        help = "When set, doc comments will be removed from the output file, ignoring the behaviour specified by the config file."
+    )]
+    pub remove_doc_comments: bool,
+
+    #[options(
+        short = "a",
+        help = "When set, annotations will be kept in the output file, ignoring the behaviour specified by the config file."
+    )]
+    pub keep_annotations: bool,
+
+    #[options(
+        short = "A",
+        help = "When set, annotations will be removed from the output file, ignoring the behaviour specified by the config file."
+    )]
+    pub remove_annotations: bool,
+
+    #[options(
+        no_short,
+        meta = "REGEX",
+        help = "Keeps the comments whose text matches REGEX, in addition to those specified by the config file. Can be given several times."
+    )]
+    pub keep_comment: Vec<Pattern>,
+
+    #[options(
+        no_short,
+        help = "When set, generated files are processed like any other file, instead of being removed entirely."
+    )]
+    pub keep_generated: bool,
+
+    #[options(
+        short = "m",
+        help = "When set, removed comments and strings are replaced with spaces, so that columns and byte offsets are preserved."
+    )]
+    pub mask: bool,
+
+    #[options(
+        no_short,
+        meta = "comments|strings|docs",
+        help = "When set, only keeps comments, strings or doc comments, removing the code instead."
+    )]
+    pub only: Option<Only>,
+
+    #[options(
+        short = "i",
+        help = "In search mode, makes the pattern case-insensitive."
+    )]
+    pub ignore_case: bool,
+
+    #[options(
+        no_short,
+        help = "In search mode, also search hidden files and directories."
+    )]
+    pub hidden: bool,
+
+    #[options(
+        no_short,
+        help = "In search mode, don't respect .gitignore and .ignore files."
+    )]
+    pub no_ignore: bool,
+}
+
+impl RuntimeConfig {
+    /// The file to process, when not running a subcommand.
+    pub fn filename(&self) -> Option<&str> {
+        self.args.first().map(String::as_str)
+    }
+}
@@ -0,0 +1,208 @@
+//! In-process alternative to `rg --pre just-the-code`: walks the given paths, strips each file in memory
+//! and matches a regex against the stripped lines, without having to spawn one process per file.
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use ignore::{WalkBuilder, WalkState};
+use regex::{Regex, RegexBuilder};
+
+use crate::*;
+
+/// Searches for `args[0]` in the files found under `args[1..]` (or the current directory),
+/// printing matching lines as `path:line:text`. Returns whether or not any line matched.
+pub fn search(config: &Config, runtime_config: &RuntimeConfig, args: &[String]) -> bool {
+    let Some((pattern, paths)) = args.split_first() else {
+        eprintln!(
+            "No search pattern specified. Run `{} --help` for more information.",
+            program_name()
+        );
+        std::process::exit(2);
+    };
+
+    if runtime_config.language.is_some() {
+        eprintln!("--language cannot be used in search mode, where each file is stripped according to its own language.");
+        std::process::exit(2);
+    }
+
+    let regex = match RegexBuilder::new(pattern)
+        .case_insensitive(runtime_config.ignore_case)
+        .build()
+    {
+        Ok(regex) => regex,
+        Err(error) => {
+            eprintln!("Invalid search pattern: {}", error);
+            std::process::exit(2);
+        }
+    };
+
+    // Resolve the runtime options and compile the languages once, instead of once per file
+    let strippers = config
+        .langs
+        .values()
+        .rev()
+        .map(|lang_config| {
+            let mut lang_config = lang_config.clone();
+            copy_config(&mut lang_config, config, runtime_config);
+            Stripper::new(lang_config)
+        })
+        .collect::<Vec<_>>();
+
+    let strip_prefix = paths.is_empty();
+    let mut walker = match paths.split_first() {
+        Some((first, rest)) => {
+            let mut walker = WalkBuilder::new(first);
+            for path in rest {
+                walker.add(path);
+            }
+            walker
+        }
+        None => WalkBuilder::new("."),
+    };
+    walker
+        .hidden(!runtime_config.hidden)
+        .ignore(!runtime_config.no_ignore)
+        .git_ignore(!runtime_config.no_ignore)
+        .git_global(!runtime_config.no_ignore)
+        .git_exclude(!runtime_config.no_ignore)
+        .parents(!runtime_config.no_ignore);
+
+    let found = AtomicBool::new(false);
+
+    walker.build_parallel().run(|| {
+        let strippers = &strippers;
+        let regex = &regex;
+        let found = &found;
+
+        Box::new(move |entry| {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    return WalkState::Continue;
+                }
+            };
+
+            if !entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file())
+            {
+                return WalkState::Continue;
+            }
+
+            let mut path = entry.path();
+            if strip_prefix {
+                path = path.strip_prefix("./").unwrap_or(path);
+            }
+
+            match search_file(config, runtime_config, strippers, regex, path) {
+                Ok(output) if !output.is_empty() => {
+                    found.store(true, Ordering::Relaxed);
+                    let result = std::io::stdout().lock().write_all(&output);
+                    exit_on_error(result, "Error writing search results");
+                }
+                Ok(_) => {}
+                Err(error) if error.kind() == std::io::ErrorKind::InvalidData => {
+                    // Binary or non-UTF-8 files are skipped, like ripgrep does
+                }
+                Err(error) => {
+                    eprintln!("{}: {}", path.display(), error);
+                }
+            }
+
+            WalkState::Continue
+        })
+    });
+
+    found.load(Ordering::Relaxed)
+}
+
+/// Strips the file at `path` and returns the formatted matching lines.
+/// `strippers` should be ordered from the highest to the lowest precedence.
+fn search_file(
+    config: &Config,
+    runtime_config: &RuntimeConfig,
+    strippers: &[Stripper],
+    regex: &Regex,
+    path: &Path,
+) -> std::io::Result<Vec<u8>> {
+    let contents = std::fs::read(path)?;
+    if contents.contains(&0) {
+        return Err(std::io::ErrorKind::InvalidData.into());
+    }
+
+    let filename = path.file_name().and_then(|name| name.to_str());
+    let stripper = filename.and_then(|name| {
+        strippers
+            .iter()
+            .find(|stripper| stripper.config().matches_filename(name))
+    });
+
+    let stripped = match stripper {
+        _ if filename.is_some_and(notebook::is_notebook) => {
+            let mut stripped = Vec::with_capacity(contents.len());
+            notebook::handle_notebook(config, runtime_config, contents.as_slice(), &mut stripped)?;
+            String::from_utf8(stripped)
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))?
+        }
+        Some(stripper) => {
+            let mut stripped = Vec::with_capacity(contents.len());
+            stripper.strip(contents.as_slice(), &mut stripped)?;
+            String::from_utf8(stripped).expect("Stripper::strip did not return valid UTF-8")
+        }
+        None => match detect_lang_config(config, runtime_config, &contents, &contents) {
+            Some(lang_config) => {
+                let mut stripped = Vec::with_capacity(contents.len());
+                Stripper::new(lang_config).strip(contents.as_slice(), &mut stripped)?;
+                String::from_utf8(stripped).expect("Stripper::strip did not return valid UTF-8")
+            }
+            None => String::from_utf8(contents)
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))?,
+        },
+    };
+
+    let mut output = Vec::new();
+    for (line_number, line) in stripped.lines().enumerate() {
+        if regex.is_match(line) {
+            writeln!(output, "{}:{}:{}", path.display(), line_number + 1, line)?;
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use gumdrop::Options;
+
+    use super::*;
+
+    #[test]
+    fn test_search_file() {
+        let config = Config::builtin();
+        let runtime_config = RuntimeConfig::parse_args_default::<&str>(&[]).unwrap();
+        let strippers = config
+            .langs
+            .values()
+            .rev()
+            .map(|lang_config| Stripper::new(lang_config.clone()))
+            .collect::<Vec<_>>();
+        let regex = Regex::new("needle").unwrap();
+
+        let dir = std::env::temp_dir().join(format!("just-the-code-search-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let code = dir.join("code.rs");
+        std::fs::write(&code, "// needle\nlet needle = \"needle\"; // needle\n").unwrap();
+        let binary = dir.join("binary.rs");
+        std::fs::write(&binary, b"let needle = 1;\0\n").unwrap();
+
+        let found = search_file(&config, &runtime_config, &strippers, &regex, &code);
+        let skipped = search_file(&config, &runtime_config, &strippers, &regex, &binary);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let expected = format!("{}:2:let needle = \"…\"; \n", code.display());
+        assert_eq!(String::from_utf8(found.unwrap()).unwrap(), expected);
+        assert_eq!(skipped.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+}